@@ -199,12 +199,23 @@ fn laser_test(resp: rust_game_base::Response) -> Result<()> {
             let actionresults = &mpair.1;
             for a in actionresults {
                 match a {
+                    // Same format as rust_game_base/testdata/laser.txt.
                     rust_game_base::ActionResult::Laser {
                         opponent,
                         power,
                         intensity,
                         _3,
-                    } => write!(&mut logfile, "pow={} int={} _3={}\n", power, intensity, _3)?,
+                    } => write!(
+                        &mut logfile,
+                        "{} {} {} {} {} {} {}\n",
+                        mpair.0.position.x,
+                        mpair.0.position.y,
+                        opponent.x,
+                        opponent.y,
+                        power,
+                        intensity,
+                        _3
+                    )?,
                     _ => (),
                 }
             }
@@ -224,7 +235,6 @@ fn laser_test(resp: rust_game_base::Response) -> Result<()> {
                     })
                     .collect::<Vec<_>>(),
             );
-            write!(&mut logfile, "x={} y={} ", relvec.x, relvec.y)?
         }
         turn += 1;
         res = rust_game_base::send_command_request(&mut next_actions.into_iter())?;
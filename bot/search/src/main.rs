@@ -35,7 +35,7 @@ fn main() {
     let mut state = res.current_state.unwrap();
 
     loop {
-        let ab_result = alpha_beta(&state, my_role, 1, true);
+        let ab_result = alpha_beta(&resp.stage_data, &state, my_role, 1, true);
     }
 }
 
@@ -83,10 +83,16 @@ impl ABResult {
 }
 
 // choose best move and next action.
-fn alpha_beta(state: &CurrentState, my_role: Role, depth: usize, need_move: bool) -> ABResult {
+fn alpha_beta(
+    stage: &StageData,
+    state: &CurrentState,
+    my_role: Role,
+    depth: usize,
+    need_move: bool,
+) -> ABResult {
     let your_role = my_role.opposite();
 
-    if let Some(winner) = get_winner(&state) {
+    if let Some(winner) = get_winner(stage, &state) {
         return win_result(winner);
     }
 
@@ -111,10 +117,13 @@ fn alpha_beta(state: &CurrentState, my_role: Role, depth: usize, need_move: bool
         for b in Solver::action_cands(state, my_role.opposite()) {
             let mut v = a.clone();
             v.append(&mut b.clone());
-            let (_, next_state) = state_update(state, &v);
+            let next_state = match state_update(stage, state, &v) {
+                Ok((_, next_state)) => next_state,
+                Err(_) => continue,
+            };
 
             // evaluate next state
-            let cur_res = alpha_beta(&next_state, my_role, depth - 1, false);
+            let cur_res = alpha_beta(stage, &next_state, my_role, depth - 1, false);
 
             if cur_res.is_better_than_for(&your_best, your_role) {
                 your_best = cur_res;
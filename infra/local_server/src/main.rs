@@ -0,0 +1,162 @@
+// Copyright 2020 Google LLC
+// Copyright 2020 Team Spacecat
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod server;
+
+use anyhow::{anyhow, Result};
+use clap::{App, Arg};
+use rand::SeedableRng;
+use rust_game_base::Role;
+use server::{Config, Server};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+
+// Runs a bot process and relays its requests to the server until it exits.
+fn run_bot(server: Arc<Server>, path: String, player_key: i128, role: Role) -> Result<()> {
+    let mut child = Command::new(&path)
+        .arg(player_key.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("failed to run {}: {}", path, e))?;
+    let mut stdin = child.stdin.take().unwrap();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    for line in stdout.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            break;
+        }
+        let resp = server.handle(&line);
+        if writeln!(stdin, "{}", resp).is_err() {
+            break;
+        }
+    }
+    server.abort(role);
+    drop(stdin);
+    child.wait()?;
+    Ok(())
+}
+
+fn run_match(server: Arc<Server>, attacker: String, defender: String) -> Result<()> {
+    let (attacker_key, defender_key) = server.player_keys();
+    let s = server.clone();
+    let t = thread::spawn(move || run_bot(s, attacker, attacker_key, Role::ATTACKER));
+    run_bot(server.clone(), defender, defender_key, Role::DEFENDER)?;
+    t.join().unwrap()?;
+    match server.winner() {
+        Some(Role::ATTACKER) => println!("attacker (first bot) wins"),
+        _ => println!("defender (second bot) wins"),
+    }
+    Ok(())
+}
+
+fn serve(server: Arc<Server>, port: u16) -> Result<()> {
+    let http = tiny_http::Server::http(("127.0.0.1", port))
+        .map_err(|e| anyhow!("failed to listen on {}: {}", port, e))?;
+    eprintln!("listening on http://localhost:{}", port);
+    for mut req in http.incoming_requests() {
+        let server = server.clone();
+        // START and COMMAND block until the other player arrives.
+        thread::spawn(move || {
+            let resp = if req.url().split('?').next() == Some("/aliens/send") {
+                let mut body = String::new();
+                match req.as_reader().read_to_string(&mut body) {
+                    Ok(_) => tiny_http::Response::from_string(server.handle(&body)),
+                    Err(_) => tiny_http::Response::from_string("").with_status_code(400),
+                }
+            } else {
+                tiny_http::Response::from_string("").with_status_code(404)
+            };
+            let _ = req.respond(resp);
+        });
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let matches = App::new("local_server")
+        .about("A local game server speaking the /aliens/send protocol")
+        .arg(
+            Arg::with_name("port")
+                .long("port")
+                .takes_value(true)
+                .default_value("8080"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .help("random seed for player keys and initial positions"),
+        )
+        .arg(
+            Arg::with_name("turns")
+                .long("turns")
+                .takes_value(true)
+                .default_value("256"),
+        )
+        .arg(
+            Arg::with_name("gravity-radius")
+                .long("gravity-radius")
+                .takes_value(true)
+                .default_value("16"),
+        )
+        .arg(
+            Arg::with_name("stage-half-size")
+                .long("stage-half-size")
+                .takes_value(true)
+                .default_value("128"),
+        )
+        .arg(
+            Arg::with_name("attacker")
+                .long("attacker")
+                .takes_value(true)
+                .requires("defender")
+                .help("attacker bot binary; runs a match without listening"),
+        )
+        .arg(
+            Arg::with_name("defender")
+                .long("defender")
+                .takes_value(true)
+                .requires("attacker")
+                .help("defender bot binary"),
+        )
+        .get_matches();
+
+    let config = Config {
+        total_turns: matches.value_of("turns").unwrap().parse()?,
+        gravity_radius: matches.value_of("gravity-radius").unwrap().parse()?,
+        stage_half_size: matches.value_of("stage-half-size").unwrap().parse()?,
+        ..Config::default()
+    };
+    let mut rng = match matches.value_of("seed") {
+        Some(seed) => rand::rngs::StdRng::seed_from_u64(seed.parse()?),
+        None => rand::rngs::StdRng::from_entropy(),
+    };
+    let server = Arc::new(Server::new(config, &mut rng));
+    let (attacker_key, defender_key) = server.player_keys();
+    eprintln!(
+        "player keys: attacker={} defender={}",
+        attacker_key, defender_key
+    );
+
+    match (matches.value_of("attacker"), matches.value_of("defender")) {
+        (Some(attacker), Some(defender)) => {
+            run_match(server, attacker.to_string(), defender.to_string())
+        }
+        _ => serve(server, matches.value_of("port").unwrap().parse()?),
+    }
+}
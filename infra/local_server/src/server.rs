@@ -0,0 +1,468 @@
+// Copyright 2020 Google LLC
+// Copyright 2020 Team Spacecat
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{anyhow, bail, Result};
+use rand::Rng;
+use rust_game_base::arena::Arena;
+use rust_game_base::dsl::*;
+use rust_game_base::list;
+use rust_game_base::value::*;
+use rust_game_base::*;
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub total_turns: usize,
+    pub gravity_radius: usize,
+    pub stage_half_size: usize,
+    pub attacker_cost: usize,
+    pub defender_cost: usize,
+    pub heat_limit: usize,
+    pub thrust_limit: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            total_turns: 256,
+            gravity_radius: 16,
+            stage_half_size: 128,
+            attacker_cost: 512,
+            defender_cost: 448,
+            heat_limit: 64,
+            thrust_limit: 1,
+        }
+    }
+}
+
+// Cost of each param in InitializeParam::total_cost, or None if it overflows.
+fn param_cost(p: &Param) -> Option<usize> {
+    p.energy
+        .checked_add(p.laser_power.checked_mul(4)?)?
+        .checked_add(p.cool_down_per_turn.checked_mul(12)?)?
+        .checked_add(p.life.checked_mul(2)?)
+}
+
+struct Player {
+    key: i128,
+    role: Role,
+    params: Option<Param>,
+    commands: Option<Vec<Command>>,
+}
+
+struct Match {
+    players: Vec<Player>,
+    game_state: CurrentGameState,
+    state: Option<CurrentState>,
+    winner: Option<Role>,
+}
+
+impl Match {
+    fn player_index(&self, player_key: i128) -> Result<usize> {
+        self.players
+            .iter()
+            .position(|p| p.key == player_key)
+            .ok_or_else(|| anyhow!("unknown player key: {}", player_key))
+    }
+
+    fn finish(&mut self, winner: Role) {
+        if self.game_state != CurrentGameState::END {
+            self.game_state = CurrentGameState::END;
+            self.winner = Some(winner);
+        }
+    }
+}
+
+// A game server for a single match between an attacker and a defender.
+pub struct Server {
+    config: Config,
+    // Initial positions of the defender and the attacker.
+    positions: (Point, Point),
+    m: Mutex<Match>,
+    cv: Condvar,
+}
+
+impl Server {
+    pub fn new(config: Config, rng: &mut impl Rng) -> Server {
+        let mut key = || rng.gen_range(1, i64::MAX) as i128;
+        let players = vec![
+            Player {
+                key: key(),
+                role: Role::ATTACKER,
+                params: None,
+                commands: None,
+            },
+            Player {
+                key: key(),
+                role: Role::DEFENDER,
+                params: None,
+                commands: None,
+            },
+        ];
+        // Both start on the opposite sides, outside of the gravity.
+        let near = config.gravity_radius as isize * 2;
+        let far = std::cmp::max(near + 1, config.stage_half_size as isize / 2);
+        let x = rng.gen_range(near, far);
+        let y = rng.gen_range(-x, x + 1);
+        let (x, y) = if rng.gen() { (x, y) } else { (y, x) };
+        Server {
+            config,
+            positions: (Point::new(x, y), Point::new(-x, -y)),
+            m: Mutex::new(Match {
+                players,
+                game_state: CurrentGameState::START,
+                state: None,
+                winner: None,
+            }),
+            cv: Condvar::new(),
+        }
+    }
+
+    // Returns the player keys of the attacker and the defender.
+    pub fn player_keys(&self) -> (i128, i128) {
+        let m = self.m.lock().unwrap();
+        (m.players[0].key, m.players[1].key)
+    }
+
+    // Returns the winner once the match is over.
+    pub fn winner(&self) -> Option<Role> {
+        self.m.lock().unwrap().winner
+    }
+
+    // Ends the match because the player of `role` is gone.
+    pub fn abort(&self, role: Role) {
+        let mut m = self.m.lock().unwrap();
+        if m.game_state != CurrentGameState::END {
+            eprintln!("server: {:?} left the match", role);
+        }
+        m.finish(role.opposite());
+        self.cv.notify_all();
+    }
+
+    // Handles a modulated request and returns the modulated response.
+    // Blocks until the other player catches up for START and COMMAND.
+    pub fn handle(&self, body: &str) -> String {
+        let resp = Arena::new()
+            .demodulate(body.trim())
+            .map_err(ProtocolError::from)
+            .and_then(parse_request_from)
+            .map_err(anyhow::Error::from)
+            .and_then(|req| self.handle_request(req));
+        match resp {
+            Ok(val) => modulate_to_string(&val),
+            Err(e) => {
+                eprintln!("server: {:#}", e);
+                modulate_to_string(&list!(int(0)))
+            }
+        }
+    }
+
+    pub fn handle_request(&self, req: Request) -> Result<Value> {
+        match req {
+            Request::Create => {
+                let (attacker, defender) = self.player_keys();
+                Ok(list!(
+                    int(1),
+                    list!(list!(int(0), int(attacker)), list!(int(1), int(defender)))
+                ))
+            }
+            Request::Join { player_key } => {
+                let m = self.m.lock().unwrap();
+                let i = m.player_index(player_key)?;
+                Ok(self.response(&m, i).to_value())
+            }
+            Request::Start { player_key, params } => self.start(player_key, params),
+            Request::Command {
+                player_key,
+                commands,
+            } => self.command(player_key, commands),
+        }
+    }
+
+    fn stage_data(&self, m: &Match, role: Role) -> StageData {
+        StageData {
+            total_turns: self.config.total_turns,
+            self_role: role,
+            initialize_param: InitializeParam {
+                total_cost: match role {
+                    Role::ATTACKER => self.config.attacker_cost,
+                    Role::DEFENDER => self.config.defender_cost,
+                },
+                thrust_limit: self.config.thrust_limit,
+                heat_limit: self.config.heat_limit,
+            },
+            obstacle: Some(self.obstacle()),
+            defender: match role {
+                Role::ATTACKER => m.players[1].params,
+                Role::DEFENDER => None,
+            },
+        }
+    }
+
+    fn obstacle(&self) -> Obstacle {
+        Obstacle {
+            gravity_radius: self.config.gravity_radius,
+            stage_half_size: self.config.stage_half_size,
+        }
+    }
+
+    fn response(&self, m: &Match, i: usize) -> Response {
+        Response {
+            current_game_state: m.game_state,
+            stage_data: self.stage_data(m, m.players[i].role),
+            current_state: m.state.clone(),
+        }
+    }
+
+    fn wait_while<'a>(
+        &self,
+        mut m: MutexGuard<'a, Match>,
+        cond: impl Fn(&Match) -> bool,
+    ) -> MutexGuard<'a, Match> {
+        while m.game_state != CurrentGameState::END && cond(&m) {
+            m = self.cv.wait(m).unwrap();
+        }
+        m
+    }
+
+    fn start(&self, player_key: i128, params: Option<Param>) -> Result<Value> {
+        let mut m = self.m.lock().unwrap();
+        let i = m.player_index(player_key)?;
+        if m.players[i].params.is_some() {
+            bail!("player {} has already started", player_key);
+        }
+        let role = m.players[i].role;
+        let total_cost = self.stage_data(&m, role).initialize_param.total_cost;
+        // The tutorial mode sends no params; give a ship that can stay.
+        let stay = Param {
+            energy: 0,
+            laser_power: 0,
+            cool_down_per_turn: 8,
+            life: 1,
+        };
+        let params = params.unwrap_or(Param {
+            energy: param_cost(&stay)
+                .and_then(|cost| total_cost.checked_sub(cost))
+                .unwrap_or(0),
+            ..stay
+        });
+        let affordable = matches!(param_cost(&params), Some(cost) if cost <= total_cost);
+        if !affordable || params.life == 0 {
+            eprintln!("server: {:?} sent invalid params {:?}", role, params);
+            m.finish(role.opposite());
+            self.cv.notify_all();
+            return Ok(self.response(&m, i).to_value());
+        }
+        m.players[i].params = Some(params);
+
+        if m.players.iter().all(|p| p.params.is_some()) {
+            m.state = Some(self.initial_state(&m));
+            m.game_state = CurrentGameState::PLAYING;
+            self.cv.notify_all();
+        }
+        let m = self.wait_while(m, |m| m.state.is_none());
+        Ok(self.response(&m, i).to_value())
+    }
+
+    fn initial_state(&self, m: &Match) -> CurrentState {
+        let machine = |role, machine_id, position, params| Machine {
+            role,
+            machine_id,
+            position,
+            velocity: Point::new(0, 0),
+            params,
+            heat: 0,
+            heat_limit: self.config.heat_limit,
+            move_limit: self.config.thrust_limit,
+        };
+        CurrentState {
+            turn: 0,
+            obstacle: Some(self.obstacle()),
+            machines: vec![
+                (
+                    machine(
+                        Role::DEFENDER,
+                        0,
+                        self.positions.0,
+                        m.players[1].params.unwrap(),
+                    ),
+                    vec![],
+                ),
+                (
+                    machine(
+                        Role::ATTACKER,
+                        1,
+                        self.positions.1,
+                        m.players[0].params.unwrap(),
+                    ),
+                    vec![],
+                ),
+            ],
+        }
+    }
+
+    fn command(&self, player_key: i128, commands: Vec<Command>) -> Result<Value> {
+        let mut m = self.m.lock().unwrap();
+        let i = m.player_index(player_key)?;
+        if m.game_state != CurrentGameState::PLAYING {
+            return Ok(self.response(&m, i).to_value());
+        }
+        if m.players[i].commands.is_some() {
+            bail!("player {} has already sent commands", player_key);
+        }
+        m.players[i].commands = Some(commands);
+
+        let turn = m.state.as_ref().unwrap().turn;
+        if m.players.iter().all(|p| p.commands.is_some()) {
+            let res = self.advance(&mut m);
+            self.cv.notify_all();
+            res?;
+        }
+        let m = self.wait_while(m, |m| m.state.as_ref().unwrap().turn == turn);
+        Ok(self.response(&m, i).to_value())
+    }
+
+    // Ends the match without a winner if the simulator rejects the turn.
+    fn advance(&self, m: &mut Match) -> Result<()> {
+        let state = m.state.take().unwrap();
+        let mut commands = Vec::new();
+        for i in 0..m.players.len() {
+            let role = m.players[i].role;
+            let stage = self.stage_data(m, role);
+            let cmds = m.players[i].commands.take().unwrap();
+            match validate_commands(&stage, &state, role, &cmds) {
+                Ok(()) => commands.extend(cmds),
+                Err(errors) => {
+                    for e in &errors {
+                        eprintln!("server: drop {:?}: {:?} ({:?})", role, e.command, e.kind);
+                    }
+                    commands.extend(
+                        cmds.into_iter()
+                            .enumerate()
+                            .filter(|(i, _)| errors.iter().all(|e| e.index != *i))
+                            .map(|(_, c)| c),
+                    );
+                }
+            }
+        }
+        let stage = self.stage_data(m, Role::DEFENDER);
+        let (game_state, next) = match state_update(&stage, &state, &commands) {
+            Ok(res) => res,
+            Err(errors) => {
+                m.game_state = CurrentGameState::END;
+                m.state = Some(state);
+                bail!("the simulator rejected validated commands: {:?}", errors);
+            }
+        };
+        if game_state == CurrentGameState::END {
+            m.finish(get_winner(&stage, &next).unwrap_or(Role::DEFENDER));
+        }
+        m.state = Some(next);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn send(server: &Server, val: Value) -> Response {
+        let resp = server.handle(&modulate_to_string(&val));
+        parse_response(demodulate_from_string(&resp).unwrap()).unwrap()
+    }
+
+    fn play(server: Arc<Server>, player_key: i128, params: Param) -> Response {
+        let resp = send(&server, list!(int(2), int(player_key), nil()));
+        assert_eq!(resp.current_game_state, CurrentGameState::START);
+        let mut resp = send(&server, list!(int(3), int(player_key), params.to_value()));
+        while resp.current_game_state == CurrentGameState::PLAYING {
+            let state = resp.current_state.as_ref().unwrap();
+            // Keep away from the planet by thrusting against the gravity.
+            let commands = get_roled_machine_ids(state, resp.stage_data.self_role)
+                .into_iter()
+                .filter_map(|id| actions::stay(state, id))
+                .map(|c| c.to_value())
+                .collect();
+            resp = send(&server, list!(int(4), int(player_key), from_vec(commands)));
+        }
+        resp
+    }
+
+    #[test]
+    fn test_match() {
+        let config = Config {
+            total_turns: 10,
+            ..Config::default()
+        };
+        let server = Arc::new(Server::new(
+            config,
+            &mut rand::rngs::StdRng::seed_from_u64(1),
+        ));
+        let (attacker, defender) = server.player_keys();
+        let params = Param {
+            energy: 100,
+            laser_power: 0,
+            cool_down_per_turn: 8,
+            life: 1,
+        };
+
+        let s = server.clone();
+        let t = thread::spawn(move || play(s, attacker, params));
+        let resp = play(server.clone(), defender, params);
+        let attacker_resp = t.join().unwrap();
+
+        assert_eq!(resp.current_game_state, CurrentGameState::END);
+        assert_eq!(resp.current_state.as_ref().unwrap().turn, 10);
+        assert_eq!(attacker_resp.current_state, resp.current_state);
+        assert_eq!(attacker_resp.stage_data.defender, Some(params));
+        assert_eq!(server.winner(), Some(Role::DEFENDER));
+    }
+
+    #[test]
+    fn test_overflowing_params() {
+        let server = Server::new(Config::default(), &mut rand::rngs::StdRng::seed_from_u64(1));
+        let (attacker, _) = server.player_keys();
+        let params = Param {
+            energy: 1,
+            laser_power: usize::MAX / 2,
+            cool_down_per_turn: 0,
+            life: 1,
+        };
+        let resp = send(&server, list!(int(3), int(attacker), params.to_value()));
+        assert_eq!(resp.current_game_state, CurrentGameState::END);
+        assert_eq!(server.winner(), Some(Role::DEFENDER));
+    }
+
+    #[test]
+    fn test_invalid_request() {
+        let server = Server::new(Config::default(), &mut rand::rngs::StdRng::seed_from_u64(1));
+        // JOIN with an unknown player key.
+        let join = list!(int(2), int(42), nil());
+        assert_eq!(server.handle(&modulate_to_string(&join)), "1101000");
+        assert_eq!(server.handle("garbage"), "1101000");
+        let resp = server.handle("11011000011101000");
+        let (attacker, defender) = server.player_keys();
+        assert_eq!(
+            resp,
+            modulate_to_string(&list!(
+                int(1),
+                list!(list!(int(0), int(attacker)), list!(int(1), int(defender)))
+            ))
+        );
+    }
+}
@@ -0,0 +1,61 @@
+// Copyright 2020 Google LLC
+// Copyright 2020 Team Spacecat
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::Result;
+use clap::{App, Arg};
+use rust_game_base::conformance::{check_replay, Report};
+use rust_game_base::replay::Replay;
+
+fn main() -> Result<()> {
+    let matches = App::new("replay_check")
+        .about("Checks the simulator against recorded replays")
+        .arg(
+            Arg::with_name("summary")
+                .long("summary")
+                .help("prints the number of differences per rule only"),
+        )
+        .arg(Arg::with_name("REPLAY").required(true).multiple(true))
+        .get_matches();
+
+    let mut total = Report::default();
+    for path in matches.values_of("REPLAY").unwrap() {
+        let report = check_replay(&Replay::load(path)?);
+        if !matches.is_present("summary") {
+            for (turn, errors) in &report.rejected {
+                for e in errors {
+                    println!(
+                        "{}: turn {} rejected {:?} ({:?})",
+                        path, turn, e.command, e.kind
+                    );
+                }
+            }
+            for d in &report.diffs {
+                println!("{}: {}", path, d);
+            }
+        }
+        total.merge(report);
+    }
+
+    println!(
+        "{} turns, {} differences, {} rejected turns",
+        total.turns,
+        total.diffs.len(),
+        total.rejected.len()
+    );
+    for (rule, count) in total.summary() {
+        println!("{:?}: {}", rule, count);
+    }
+    Ok(())
+}
@@ -0,0 +1,116 @@
+// Copyright 2020 Google LLC
+// Copyright 2020 Team Spacecat
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// cargo bench --bench value
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rust_game_base::arena::Arena;
+use rust_game_base::value::*;
+use rust_game_base::*;
+
+// A galaxy-like payload: a long list of (x . y) pixels.
+fn pixels(n: usize) -> Value {
+    from_vec(
+        (0..n as i128)
+            .map(|i| Value::Cons(Box::new(Value::Int(i % 512)), Box::new(Value::Int(-i))))
+            .collect(),
+    )
+}
+
+// The worst case for recursion: every car is another cell.
+fn deep_car(n: usize) -> Value {
+    (0..n).fold(Value::Nil, |acc, i| {
+        Value::Cons(Box::new(acc), Box::new(Value::Int(i as i128)))
+    })
+}
+
+// A response with more machines and results than a real match ever has.
+fn response() -> Response {
+    let machine = |machine_id| Machine {
+        machine_id,
+        position: Point::new(48, -machine_id),
+        ..Machine::default()
+    };
+    let results = vec![
+        ActionResult::Thruster {
+            a: Point::new(1, 0),
+        },
+        ActionResult::Laser {
+            opponent: Point::new(-48, 10),
+            power: 64,
+            intensity: 100,
+            _3: 4,
+        },
+    ];
+    Response {
+        current_game_state: CurrentGameState::PLAYING,
+        stage_data: StageData {
+            total_turns: 256,
+            self_role: Role::ATTACKER,
+            initialize_param: InitializeParam {
+                total_cost: 512,
+                thrust_limit: 1,
+                heat_limit: 64,
+            },
+            obstacle: None,
+            defender: None,
+        },
+        current_state: Some(CurrentState {
+            turn: 100,
+            obstacle: None,
+            machines: (0..64).map(|id| (machine(id), results.clone())).collect(),
+        }),
+    }
+}
+
+fn bench(c: &mut Criterion) {
+    for &(name, ref val) in [("pixels", pixels(100_000)), ("deep_car", deep_car(100_000))].iter() {
+        let bits = modulate_to_string(val);
+        let mut group = c.benchmark_group(name);
+        group.sample_size(10);
+        // One byte per bit of the modulated string, a few megabits each.
+        group.throughput(Throughput::Bytes(bits.len() as u64));
+        group.bench_function("modulate", |b| b.iter(|| modulate_to_string(val)));
+        group.bench_function("demodulate", |b| {
+            b.iter(|| try_demodulate_from_string(&bits).unwrap())
+        });
+        group.bench_function("to_string", |b| b.iter(|| val.to_string()));
+        group.bench_function("clone_and_drop", |b| b.iter(|| val.clone()));
+        group.finish();
+    }
+    let list = pixels(100_000);
+    c.bench_function("pixels/to_vec", |b| {
+        b.iter_batched(
+            || list.clone(),
+            |val| to_vec(val).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+
+    // What GameClient does with every response.
+    let bits = modulate_to_string(&response().to_value());
+    let mut group = c.benchmark_group("response");
+    group.bench_function("value", |b| {
+        b.iter(|| parse_response(try_demodulate_from_string(&bits).unwrap()).unwrap())
+    });
+    let mut arena = Arena::new();
+    group.bench_function("arena", |b| {
+        b.iter(|| parse_response_from(arena.demodulate(&bits).unwrap()).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
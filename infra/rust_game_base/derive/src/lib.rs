@@ -0,0 +1,313 @@
+// Copyright 2020 Google LLC
+// Copyright 2020 Team Spacecat
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// #[derive(ToValue, FromValue)] for rust_game_base::convert.
+//
+// - A struct is a list of its fields: [field0, field1, ...].
+//   #[value(tag = N)] on the struct puts N in front: [N, field0, ...].
+// - An enum of unit variants is its tag, an integer.
+// - Any other enum is [tag, field0, field1, ...].
+// The tag of a variant is its index unless #[value(tag = N)] says otherwise.
+//
+// On a field, #[value(name = "x")] names it "x" in error paths, which tuple
+// fields otherwise get by position, and #[value(with = "m")] encodes it with
+// m::to_value(&field) and decodes it with m::from_view(val) instead of the
+// ToValue/FromValue impls of its type.
+
+extern crate proc_macro;
+
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Fields, Ident, Lit, Meta, NestedMeta};
+
+// Reads `key` of #[value(key = ..)].
+fn attr(attrs: &[Attribute], key: &str) -> Option<Lit> {
+    for attr in attrs {
+        if !attr.path.is_ident("value") {
+            continue;
+        }
+        let list = match attr.parse_meta() {
+            Ok(Meta::List(list)) => list,
+            _ => panic!("expected #[value(key = ..)]"),
+        };
+        for nested in list.nested.iter() {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(nv)) => {
+                    if nv.path.is_ident(key) {
+                        return Some(nv.lit.clone());
+                    }
+                }
+                _ => panic!("expected #[value(key = ..)]"),
+            }
+        }
+    }
+    None
+}
+
+// Reads #[value(tag = N)].
+fn tag(attrs: &[Attribute]) -> Option<i128> {
+    attr(attrs, "tag").map(|lit| match lit {
+        Lit::Int(n) => n.base10_parse().expect("tag must be an integer"),
+        _ => panic!("tag must be an integer"),
+    })
+}
+
+fn str_attr(attrs: &[Attribute], key: &str) -> Option<String> {
+    attr(attrs, key).map(|lit| match lit {
+        Lit::Str(s) => s.value(),
+        _ => panic!("{} must be a string", key),
+    })
+}
+
+// The bindings for the fields, their path segments in errors, their
+// #[value(with = ..)] modules and a description of the list for
+// "expected ..." messages.
+struct FieldsInfo {
+    bindings: Vec<Ident>,
+    segments: Vec<String>,
+    with: Vec<Option<syn::Path>>,
+    expected: String,
+}
+
+fn fields_info(fields: &Fields, tag: Option<i128>) -> FieldsInfo {
+    let offset = if tag.is_some() { 1 } else { 0 };
+    let mut bindings = vec![];
+    let mut segments = vec![];
+    let mut with = vec![];
+    for (i, f) in fields.iter().enumerate() {
+        let name = str_attr(&f.attrs, "name");
+        match &f.ident {
+            Some(ident) => {
+                bindings.push(ident.clone());
+                segments.push(name.unwrap_or_else(|| ident.to_string()));
+            }
+            None => {
+                bindings.push(Ident::new(&format!("f{}", i), Span::call_site()));
+                segments.push(name.unwrap_or_else(|| format!("[{}]", i + offset)));
+            }
+        }
+        with.push(
+            str_attr(&f.attrs, "with")
+                .map(|path| syn::parse_str(&path).expect("with must be a path")),
+        );
+    }
+    let mut names = tag.map(|t| t.to_string()).into_iter().collect::<Vec<_>>();
+    names.extend(segments.iter().enumerate().map(|(i, s)| {
+        if s.starts_with('[') {
+            format!("_{}", i)
+        } else {
+            s.clone()
+        }
+    }));
+    FieldsInfo {
+        bindings,
+        segments,
+        with,
+        expected: format!("[{}]", names.join(", ")),
+    }
+}
+
+// A pattern or constructor for `fields` with the given bindings.
+fn shape(path: TokenStream, fields: &Fields, bindings: &[Ident]) -> TokenStream {
+    match fields {
+        Fields::Named(_) => quote!(#path { #(#bindings),* }),
+        Fields::Unnamed(_) => quote!(#path ( #(#bindings),* )),
+        Fields::Unit => quote!(#path),
+    }
+}
+
+fn encode_list(tag: Option<i128>, info: &FieldsInfo) -> TokenStream {
+    let tag = tag.map(|t| quote!(::rust_game_base::value::Value::Int(#t),));
+    let items = info
+        .bindings
+        .iter()
+        .zip(&info.with)
+        .map(|(b, with)| match with {
+            Some(with) => quote!(#with::to_value(#b)),
+            None => quote!(::rust_game_base::convert::ToValue::to_value(#b)),
+        });
+    quote! {
+        ::rust_game_base::value::from_vec(vec![
+            #tag
+            #(#items),*
+        ])
+    }
+}
+
+// Decodes `items` (a slice of &Value) that already matched the length.
+fn decode_fields(
+    path: TokenStream,
+    fields: &Fields,
+    info: &FieldsInfo,
+    offset: usize,
+) -> TokenStream {
+    let bindings = &info.bindings;
+    let segments = &info.segments;
+    let decoders = info.with.iter().map(|with| match with {
+        Some(with) => quote!(#with::from_view),
+        None => quote!(::rust_game_base::convert::FromValue::from_view),
+    });
+    let indices = (0..bindings.len()).map(|i| i + offset);
+    let constructor = shape(path, fields, bindings);
+    quote! {{
+        #(
+            let #bindings = #decoders(items[#indices]).map_err(|e| e.within(#segments))?;
+        )*
+        Ok(#constructor)
+    }}
+}
+
+fn is_unit_enum(data: &Data) -> bool {
+    match data {
+        Data::Enum(e) => e.variants.iter().all(|v| v.fields.is_empty()),
+        _ => false,
+    }
+}
+
+fn variant_tags(e: &syn::DataEnum) -> Vec<i128> {
+    e.variants
+        .iter()
+        .enumerate()
+        .map(|(i, v)| tag(&v.attrs).unwrap_or(i as i128))
+        .collect()
+}
+
+#[proc_macro_derive(ToValue, attributes(value))]
+pub fn derive_to_value(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let body = match &input.data {
+        Data::Struct(s) => {
+            let info = fields_info(&s.fields, tag(&input.attrs));
+            let pattern = shape(quote!(#name), &s.fields, &info.bindings);
+            let list = encode_list(tag(&input.attrs), &info);
+            quote! {
+                let #pattern = self;
+                #list
+            }
+        }
+        Data::Enum(e) => {
+            let tags = variant_tags(e);
+            let unit = is_unit_enum(&input.data);
+            let arms = e.variants.iter().zip(tags).map(|(v, t)| {
+                let ident = &v.ident;
+                let info = fields_info(&v.fields, Some(t));
+                let pattern = shape(quote!(#name::#ident), &v.fields, &info.bindings);
+                let val = if unit {
+                    quote!(::rust_game_base::value::Value::Int(#t))
+                } else {
+                    encode_list(Some(t), &info)
+                };
+                quote!(#pattern => #val,)
+            });
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => panic!("unions are not supported"),
+    };
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let expanded = quote! {
+        impl #impl_generics ::rust_game_base::convert::ToValue for #name #ty_generics #where_clause {
+            fn to_value(&self) -> ::rust_game_base::value::Value {
+                #body
+            }
+        }
+    };
+    expanded.into()
+}
+
+#[proc_macro_derive(FromValue, attributes(value))]
+pub fn derive_from_value(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let body = match &input.data {
+        Data::Struct(s) => {
+            let tag = tag(&input.attrs);
+            let info = fields_info(&s.fields, tag);
+            let expected = &info.expected;
+            let len = info.bindings.len() + tag.is_some() as usize;
+            let check_tag = tag.map(|t| {
+                quote! {
+                    match <i128 as ::rust_game_base::convert::FromValue>::from_view(items[0])? {
+                        #t => (),
+                        t => return Err(::rust_game_base::convert::ProtocolError::tag(t)),
+                    }
+                }
+            });
+            let decode = decode_fields(quote!(#name), &s.fields, &info, tag.is_some() as usize);
+            quote! {
+                let items = ::rust_game_base::convert::list_items(val)
+                    .ok_or_else(|| ::rust_game_base::convert::ProtocolError::shape("a list", val))?;
+                if items.len() != #len {
+                    return Err(::rust_game_base::convert::ProtocolError::shape(#expected, val));
+                }
+                #check_tag
+                #decode
+            }
+        }
+        Data::Enum(e) if is_unit_enum(&input.data) => {
+            let tags = variant_tags(e);
+            let idents = e.variants.iter().map(|v| &v.ident);
+            quote! {
+                match <i128 as ::rust_game_base::convert::FromValue>::from_view(val)? {
+                    #(#tags => Ok(#name::#idents),)*
+                    t => Err(::rust_game_base::convert::ProtocolError::tag(t)),
+                }
+            }
+        }
+        Data::Enum(e) => {
+            let tags = variant_tags(e);
+            let arms = e.variants.iter().zip(tags).map(|(v, t)| {
+                let ident = &v.ident;
+                let info = fields_info(&v.fields, Some(t));
+                let expected = &info.expected;
+                let len = info.bindings.len() + 1;
+                let decode = decode_fields(quote!(#name::#ident), &v.fields, &info, 1);
+                quote! {
+                    #t => {
+                        if items.len() != #len {
+                            return Err(::rust_game_base::convert::ProtocolError::shape(#expected, val));
+                        }
+                        #decode
+                    }
+                }
+            });
+            quote! {
+                let items = ::rust_game_base::convert::list_items(val)
+                    .filter(|items| !items.is_empty())
+                    .ok_or_else(|| ::rust_game_base::convert::ProtocolError::shape("[tag, ...]", val))?;
+                match <i128 as ::rust_game_base::convert::FromValue>::from_view(items[0])? {
+                    #(#arms)*
+                    t => Err(::rust_game_base::convert::ProtocolError::tag(t)),
+                }
+            }
+        }
+        Data::Union(_) => panic!("unions are not supported"),
+    };
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let expanded = quote! {
+        impl #impl_generics ::rust_game_base::convert::FromValue for #name #ty_generics #where_clause {
+            fn from_view<'__a, __V: ::rust_game_base::arena::ValueView<'__a>>(
+                val: __V,
+            ) -> ::std::result::Result<Self, ::rust_game_base::convert::ProtocolError> {
+                #body
+            }
+        }
+    };
+    expanded.into()
+}
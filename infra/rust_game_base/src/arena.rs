@@ -0,0 +1,324 @@
+// Copyright 2020 Google LLC
+// Copyright 2020 Team Spacecat
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Values stored in one Vec instead of a Box per cell. GameClient demodulates
+// every response into the same Arena, so a turn costs no allocation once the
+// Vec has grown, and FromValue reads the nodes in place through ValueView.
+
+use crate::value::*;
+use num_bigint::BigInt;
+use std::fmt;
+
+// A borrowed value, either a &Value or a ValueRef into an Arena.
+pub trait ValueView<'a>: Copy + fmt::Display {
+    fn view(self) -> View<'a, Self>;
+
+    // Copies the value out, without recursion.
+    fn to_owned_value(self) -> Value {
+        // Cells whose car is built and whose cdr is not.
+        let mut cells: Vec<Option<Value>> = vec![];
+        let mut todo = vec![self];
+        let mut done = vec![];
+        while let Some(v) = todo.pop() {
+            let leaf = match v.view() {
+                View::Int(n) => Value::Int(n),
+                View::BigInt(n) => Value::BigInt(n.clone()),
+                View::Nil => Value::Nil,
+                View::Cons(car, cdr) => {
+                    cells.push(None);
+                    todo.push(cdr);
+                    todo.push(car);
+                    continue;
+                }
+            };
+            done.push(leaf);
+            while let Some(val) = done.pop() {
+                match cells.pop() {
+                    None => return val,
+                    Some(None) => cells.push(Some(val)),
+                    Some(Some(car)) => {
+                        done.push(Value::Cons(Box::new(car), Box::new(val)));
+                        continue;
+                    }
+                }
+                break;
+            }
+        }
+        unreachable!("the value is built once the last leaf is seen")
+    }
+}
+
+// One level of a ValueView.
+pub enum View<'a, V> {
+    Int(i128),
+    BigInt(&'a BigInt),
+    Nil,
+    Cons(V, V),
+}
+
+impl<'a> ValueView<'a> for &'a Value {
+    fn view(self) -> View<'a, &'a Value> {
+        match self {
+            Value::Int(n) => View::Int(*n),
+            Value::BigInt(n) => View::BigInt(n),
+            Value::Nil => View::Nil,
+            Value::Cons(car, cdr) => View::Cons(car, cdr),
+        }
+    }
+
+    fn to_owned_value(self) -> Value {
+        self.clone()
+    }
+}
+
+enum Node {
+    Int(i128),
+    BigInt(BigInt),
+    Nil,
+    Cons(u32, u32),
+}
+
+#[derive(Default)]
+pub struct Arena {
+    nodes: Vec<Node>,
+}
+
+impl Arena {
+    pub fn new() -> Arena {
+        Arena::default()
+    }
+
+    fn push(&mut self, node: Node) -> u32 {
+        self.nodes.push(node);
+        (self.nodes.len() - 1) as u32
+    }
+
+    fn get(&self, index: u32) -> ValueRef<'_> {
+        ValueRef { arena: self, index }
+    }
+
+    // Replaces the contents with the demodulated `bits`, which must be
+    // exactly one value as in try_demodulate_from_string.
+    pub fn demodulate(&mut self, bits: &str) -> Result<ValueRef<'_>, DemodulateError> {
+        self.nodes.clear();
+        let bytes = bits.as_bytes();
+        let mut offset = 0;
+        let mut next = || {
+            let bit = match bytes.get(offset) {
+                Some(b'0') => false,
+                Some(b'1') => true,
+                Some(_) => return Err(DemodulateError::InvalidChar(offset)),
+                None => return Err(DemodulateError::UnexpectedEnd(offset)),
+            };
+            offset += 1;
+            Ok(bit)
+        };
+        // Cells being built: None until the car is done, then Some(car).
+        let mut cells: Vec<Option<u32>> = vec![];
+        let root = loop {
+            let mut index = match (next()?, next()?) {
+                (false, false) => self.push(Node::Nil),
+                (true, true) => {
+                    cells.push(None);
+                    continue;
+                }
+                (_, y) => match &demodulate_int(y, &mut next)? {
+                    Value::Int(n) => self.push(Node::Int(*n)),
+                    Value::BigInt(n) => self.push(Node::BigInt(n.clone())),
+                    _ => unreachable!("demodulate_int returns integers"),
+                },
+            };
+            loop {
+                match cells.pop() {
+                    None => break,
+                    Some(None) => {
+                        cells.push(Some(index));
+                        break;
+                    }
+                    Some(Some(car)) => index = self.push(Node::Cons(car, index)),
+                }
+            }
+            if cells.is_empty() {
+                break index;
+            }
+        };
+        if offset < bytes.len() {
+            return Err(DemodulateError::TrailingBits(offset));
+        }
+        Ok(self.get(root))
+    }
+
+    // Replaces the contents with a copy of `val`.
+    pub fn copy(&mut self, val: &Value) -> ValueRef<'_> {
+        self.nodes.clear();
+        // Post-order, so that every cell comes after its car and cdr.
+        let mut todo = vec![(val, false)];
+        let mut done = vec![];
+        while let Some((val, expanded)) = todo.pop() {
+            let node = match val {
+                Value::Int(n) => Node::Int(*n),
+                Value::BigInt(n) => Node::BigInt(n.clone()),
+                Value::Nil => Node::Nil,
+                Value::Cons(car, cdr) if !expanded => {
+                    todo.push((val, true));
+                    todo.push((cdr, false));
+                    todo.push((car, false));
+                    continue;
+                }
+                Value::Cons(..) => {
+                    let cdr = done.pop().unwrap();
+                    let car = done.pop().unwrap();
+                    Node::Cons(car, cdr)
+                }
+            };
+            done.push(self.push(node));
+        }
+        self.get(done[0])
+    }
+}
+
+// A node in an Arena.
+#[derive(Clone, Copy)]
+pub struct ValueRef<'a> {
+    arena: &'a Arena,
+    index: u32,
+}
+
+impl<'a> ValueView<'a> for ValueRef<'a> {
+    fn view(self) -> View<'a, ValueRef<'a>> {
+        match &self.arena.nodes[self.index as usize] {
+            Node::Int(n) => View::Int(*n),
+            Node::BigInt(n) => View::BigInt(n),
+            Node::Nil => View::Nil,
+            &Node::Cons(car, cdr) => View::Cons(self.arena.get(car), self.arena.get(cdr)),
+        }
+    }
+}
+
+impl<'a> ValueRef<'a> {
+    pub fn as_int(self) -> Option<i128> {
+        match self.view() {
+            View::Int(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_cons(self) -> Option<(ValueRef<'a>, ValueRef<'a>)> {
+        match self.view() {
+            View::Cons(car, cdr) => Some((car, cdr)),
+            _ => None,
+        }
+    }
+
+    pub fn is_nil(self) -> bool {
+        matches!(self.view(), View::Nil)
+    }
+
+    // The elements of a list. The iteration ends early at an improper tail,
+    // which `rest` then returns.
+    pub fn iter(self) -> Items<'a> {
+        Items { rest: self }
+    }
+}
+
+pub struct Items<'a> {
+    rest: ValueRef<'a>,
+}
+
+impl<'a> Items<'a> {
+    pub fn rest(&self) -> ValueRef<'a> {
+        self.rest
+    }
+}
+
+impl<'a> Iterator for Items<'a> {
+    type Item = ValueRef<'a>;
+
+    fn next(&mut self) -> Option<ValueRef<'a>> {
+        let (car, cdr) = self.rest.as_cons()?;
+        self.rest = cdr;
+        Some(car)
+    }
+}
+
+// Only used for error messages and logs, so copying out is fine.
+impl fmt::Display for ValueRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_owned_value())
+    }
+}
+
+impl fmt::Debug for ValueRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.to_owned_value())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arena() {
+        let val: Value = "(1 (2 . -3) nil 1361129467683753853853498429727072845824)"
+            .parse()
+            .unwrap();
+        let bits = modulate_to_string(&val);
+        let mut arena = Arena::new();
+        let root = arena.demodulate(&bits).unwrap();
+        assert_eq!(root.to_string(), val.to_string());
+        let items = root.iter().collect::<Vec<_>>();
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[0].as_int(), Some(1));
+        assert_eq!(items[1].iter().rest().to_string(), "(2 . -3)");
+        assert!(items[2].is_nil());
+        assert!(matches!(items[3].view(), View::BigInt(_)));
+        assert_eq!(modulate_to_string(&root.to_owned_value()), bits);
+        assert_eq!(arena.copy(&val).to_string(), val.to_string());
+
+        assert_eq!(
+            arena.demodulate("0110 0001").unwrap_err(),
+            DemodulateError::InvalidChar(4)
+        );
+        assert_eq!(
+            arena.demodulate("1101100001").unwrap_err(),
+            DemodulateError::UnexpectedEnd(10)
+        );
+        assert_eq!(
+            arena.demodulate("01000").unwrap_err(),
+            DemodulateError::TrailingBits(3)
+        );
+    }
+
+    #[test]
+    fn test_arena_deep() {
+        const DEPTH: usize = 200_000;
+        let bits = "11".repeat(DEPTH) + &"00".repeat(DEPTH + 1);
+        let mut arena = Arena::new();
+        let root = arena.demodulate(&bits).unwrap();
+        let val = root.to_owned_value();
+        let mut depth = 0;
+        let mut v = &val;
+        while let Value::Cons(car, _) = v {
+            depth += 1;
+            v = car;
+        }
+        assert_eq!(depth, DEPTH);
+        assert_eq!(
+            arena.copy(&val).as_cons().map(|(_, cdr)| cdr.is_nil()),
+            Some(true)
+        );
+    }
+}
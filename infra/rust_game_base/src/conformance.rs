@@ -0,0 +1,407 @@
+// Copyright 2020 Google LLC
+// Copyright 2020 Team Spacecat
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Replays recorded matches through the simulator and reports where it
+// disagrees with the server.
+
+use crate::game::*;
+use crate::replay::Replay;
+use crate::rules::CommandError;
+use crate::simulator::state_update;
+use std::collections::BTreeMap;
+use std::fmt;
+
+// The part of the simulator a difference is blamed on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Rule {
+    Thrust,
+    Laser,
+    Bomb,
+    Split,
+    Cooldown,
+    Gravity,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FieldDiff {
+    // The turn of the observed state that was mispredicted.
+    pub turn: usize,
+    pub machine_id: isize,
+    pub field: &'static str,
+    pub observed: String,
+    pub simulated: String,
+    pub rule: Rule,
+}
+
+impl fmt::Display for FieldDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "turn {} machine {} {}: observed {}, simulated {} ({:?})",
+            self.turn, self.machine_id, self.field, self.observed, self.simulated, self.rule
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub turns: usize,
+    pub diffs: Vec<FieldDiff>,
+    // Turns whose commands the simulator refused, by the turn of the state
+    // they were sent in.
+    pub rejected: Vec<(usize, Vec<CommandError>)>,
+}
+
+impl Report {
+    pub fn merge(&mut self, other: Report) {
+        self.turns += other.turns;
+        self.diffs.extend(other.diffs);
+        self.rejected.extend(other.rejected);
+    }
+
+    // Number of differing fields per rule.
+    pub fn summary(&self) -> BTreeMap<Rule, usize> {
+        let mut summary = BTreeMap::new();
+        for d in &self.diffs {
+            *summary.entry(d.rule).or_insert(0) += 1;
+        }
+        summary
+    }
+}
+
+// Recovers the commands executed in the previous turn from the action
+// results of `next`.
+pub fn commands_from_results(next: &CurrentState, role: Role) -> Vec<Command> {
+    let mut commands = vec![];
+    for (m, results) in &next.machines {
+        if m.role != role {
+            continue;
+        }
+        for r in results {
+            commands.push(match *r {
+                ActionResult::Thruster { a } => Command::Thrust(m.machine_id, a),
+                ActionResult::Bomb { .. } => Command::Bomb(m.machine_id),
+                ActionResult::Laser {
+                    opponent, power, ..
+                } => Command::Beam(m.machine_id, opponent, power as isize),
+                ActionResult::Split { params } => Command::Split(m.machine_id, params),
+            });
+        }
+    }
+    commands
+}
+
+fn has_result(next: &CurrentState, f: impl Fn(&ActionResult) -> bool) -> bool {
+    next.machines
+        .iter()
+        .any(|(_, results)| results.iter().any(&f))
+}
+
+// Heat and params are touched by several rules. The first rule that acted in
+// the turn, in the order bomb, laser, split, thrust, gets the blame, and
+// cooldown otherwise.
+fn blame(next: &CurrentState, results: &[ActionResult], field: &'static str) -> Rule {
+    let thrusted = results
+        .iter()
+        .any(|r| matches!(r, ActionResult::Thruster { .. }));
+    match field {
+        "position" | "velocity" => {
+            if thrusted {
+                Rule::Thrust
+            } else {
+                Rule::Gravity
+            }
+        }
+        _ => {
+            if has_result(next, |r| matches!(r, ActionResult::Bomb { .. })) {
+                Rule::Bomb
+            } else if has_result(next, |r| matches!(r, ActionResult::Laser { .. })) {
+                Rule::Laser
+            } else if results
+                .iter()
+                .any(|r| matches!(r, ActionResult::Split { .. }))
+            {
+                Rule::Split
+            } else if thrusted {
+                Rule::Thrust
+            } else {
+                Rule::Cooldown
+            }
+        }
+    }
+}
+
+fn result_rule(r: &ActionResult) -> Rule {
+    match r {
+        ActionResult::Thruster { .. } => Rule::Thrust,
+        ActionResult::Bomb { .. } => Rule::Bomb,
+        ActionResult::Laser { .. } => Rule::Laser,
+        ActionResult::Split { .. } => Rule::Split,
+    }
+}
+
+// Compares the simulated state with the observed one, machine by machine.
+pub fn diff_states(observed: &CurrentState, simulated: &CurrentState) -> Vec<FieldDiff> {
+    let mut diffs = vec![];
+    let turn = observed.turn;
+    for (m, results) in &observed.machines {
+        let diff = |field, observed: String, simulated: String, rule| FieldDiff {
+            turn,
+            machine_id: m.machine_id,
+            field,
+            observed,
+            simulated,
+            rule,
+        };
+        let (s, s_results) = match simulated
+            .machines
+            .iter()
+            .find(|(s, _)| s.machine_id == m.machine_id)
+        {
+            Some((s, r)) => (s, r),
+            None => {
+                diffs.push(diff(
+                    "machine",
+                    "present".to_string(),
+                    "missing".to_string(),
+                    Rule::Split,
+                ));
+                continue;
+            }
+        };
+        let fields: [(&'static str, String, String); 4] = [
+            (
+                "position",
+                format!("{:?}", m.position),
+                format!("{:?}", s.position),
+            ),
+            (
+                "velocity",
+                format!("{:?}", m.velocity),
+                format!("{:?}", s.velocity),
+            ),
+            ("heat", m.heat.to_string(), s.heat.to_string()),
+            (
+                "params",
+                format!("{:?}", m.params),
+                format!("{:?}", s.params),
+            ),
+        ];
+        for (field, o, s) in fields.iter().cloned() {
+            if o != s {
+                diffs.push(diff(field, o, s, blame(observed, results, field)));
+            }
+        }
+        if results != s_results {
+            let rule = results
+                .iter()
+                .chain(s_results.iter())
+                .map(result_rule)
+                .next()
+                .unwrap_or(Rule::Cooldown);
+            diffs.push(diff(
+                "results",
+                format!("{:?}", results),
+                format!("{:?}", s_results),
+                rule,
+            ));
+        }
+    }
+    for (s, _) in &simulated.machines {
+        if observed
+            .machines
+            .iter()
+            .all(|(m, _)| m.machine_id != s.machine_id)
+        {
+            diffs.push(FieldDiff {
+                turn,
+                machine_id: s.machine_id,
+                field: "machine",
+                observed: "missing".to_string(),
+                simulated: "present".to_string(),
+                rule: Rule::Split,
+            });
+        }
+    }
+    diffs
+}
+
+// Simulates every recorded turn. The recording player's commands come from
+// the replay and the opponent's are recovered from the action results.
+pub fn check_replay(replay: &Replay) -> Report {
+    let mut report = Report::default();
+    let own = replay.stage_data.self_role;
+    for w in replay.turns.windows(2) {
+        let ((state, own_commands), (next, _)) = (&w[0], &w[1]);
+        let mut commands = own_commands.clone();
+        commands.extend(commands_from_results(next, own.opposite()));
+        report.turns += 1;
+        match state_update(&replay.stage_data, state, &commands) {
+            Ok((_, simulated)) => report.diffs.extend(diff_states(next, &simulated)),
+            Err(errors) => report.rejected.push((state.turn, errors)),
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stage() -> StageData {
+        StageData {
+            total_turns: 256,
+            self_role: Role::ATTACKER,
+            initialize_param: InitializeParam {
+                total_cost: 512,
+                thrust_limit: 1,
+                heat_limit: 64,
+            },
+            obstacle: Some(Obstacle {
+                gravity_radius: 16,
+                stage_half_size: 128,
+            }),
+            defender: None,
+        }
+    }
+
+    fn initial_state() -> CurrentState {
+        let attacker = Machine {
+            role: Role::ATTACKER,
+            machine_id: 0,
+            position: Point::new(48, 10),
+            params: Param {
+                energy: 100,
+                laser_power: 16,
+                cool_down_per_turn: 8,
+                life: 1,
+            },
+            heat_limit: 64,
+            move_limit: 1,
+            ..Machine::default()
+        };
+        let defender = Machine {
+            role: Role::DEFENDER,
+            machine_id: 1,
+            position: Point::new(-48, -10),
+            ..attacker
+        };
+        CurrentState {
+            turn: 0,
+            obstacle: stage().obstacle,
+            machines: vec![(attacker, vec![]), (defender, vec![])],
+        }
+    }
+
+    #[test]
+    fn test_consistent_replay() {
+        let stage = stage();
+        let mut turns = vec![];
+        let mut state = initial_state();
+        for t in 0..4 {
+            let attacker = vec![Command::Thrust(0, Point::new(0, 1))];
+            let defender = if t == 2 {
+                vec![Command::Beam(1, Point::new(48, 10), 8)]
+            } else {
+                vec![]
+            };
+            let all = attacker.iter().chain(&defender).cloned().collect();
+            let (_, next) = state_update(&stage, &state, &all).unwrap();
+            assert_eq!(commands_from_results(&next, Role::DEFENDER), defender);
+            turns.push((state, attacker));
+            state = next;
+        }
+        turns.push((state, vec![]));
+        let replay = Replay {
+            stage_data: stage,
+            current_game_state: CurrentGameState::PLAYING,
+            turns,
+        };
+        let report = check_replay(&replay);
+        assert_eq!(report.turns, 4);
+        assert_eq!(report.diffs, vec![]);
+        assert!(report.rejected.is_empty());
+    }
+
+    // The split turns of real matches, recorded into testdata/replays as its
+    // README describes.
+    #[test]
+    #[ignore = "needs a recording from the contest server in testdata/replays"]
+    fn test_recorded_splits() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/replays");
+        let mut checked = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension() != Some("txt".as_ref()) {
+                continue;
+            }
+            let replay = Replay::load(&path).unwrap();
+            let opponent = replay.stage_data.self_role.opposite();
+            for w in replay.turns.windows(2) {
+                let ((state, own_commands), (next, _)) = (&w[0], &w[1]);
+                let is_new = |m: &Machine| {
+                    state
+                        .machines
+                        .iter()
+                        .all(|(n, _)| n.machine_id != m.machine_id)
+                };
+                if !next.machines.iter().any(|(m, _)| is_new(m)) {
+                    continue;
+                }
+                let mut commands = own_commands.clone();
+                commands.extend(commands_from_results(next, opponent));
+                let at = format!("{} turn {}", path.display(), state.turn);
+                let (_, simulated) = state_update(&replay.stage_data, state, &commands)
+                    .unwrap_or_else(|e| panic!("{}: {:?}", at, e));
+                assert_eq!(diff_states(next, &simulated), vec![], "{}", at);
+                checked += 1;
+            }
+        }
+        assert!(checked > 0, "no split turn in {}/*.txt", dir);
+    }
+
+    #[test]
+    fn test_diff_states() {
+        let state = initial_state();
+        let commands = vec![Command::Thrust(0, Point::new(1, 0))];
+        let (_, simulated) = state_update(&stage(), &state, &commands).unwrap();
+
+        let mut observed = simulated.clone();
+        observed.machines[0].0.velocity.x += 1;
+        observed.machines[0].0.heat += 4;
+        observed.machines[1].0.velocity.y -= 1;
+        let diffs = diff_states(&observed, &simulated);
+        let blamed = diffs
+            .iter()
+            .map(|d| (d.machine_id, d.field, d.rule))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            blamed,
+            vec![
+                (0, "velocity", Rule::Thrust),
+                (0, "heat", Rule::Thrust),
+                (1, "velocity", Rule::Gravity),
+            ]
+        );
+
+        let report = Report {
+            diffs,
+            ..Report::default()
+        };
+        let summary = report.summary();
+        assert_eq!(summary[&Rule::Thrust], 2);
+        assert_eq!(summary[&Rule::Gravity], 1);
+    }
+}
@@ -0,0 +1,481 @@
+// Copyright 2020 Google LLC
+// Copyright 2020 Team Spacecat
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Conversions between Value and Rust types. Structs and enums get these from
+// #[derive(ToValue, FromValue)] (see derive/src/lib.rs for the encoding):
+//
+//   #[derive(ToValue, FromValue)]
+//   struct Obstacle { gravity_radius: usize, stage_half_size: usize }
+//
+// is [gravity_radius, stage_half_size] on the wire.
+
+use crate::arena::{ValueView, View};
+use crate::value::*;
+use num_bigint::BigInt;
+use std::convert::TryFrom;
+use std::fmt::Display;
+
+// Why a server message could not be understood. Bots get these wrapped in
+// anyhow::Error and can `downcast_ref::<ProtocolError>()` to decide whether to
+// retry or give up.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ProtocolError {
+    // The bit string itself is broken.
+    BadModulation(DemodulateError),
+    // `path` (e.g. "current_state.machines[2].params") is not `expected`.
+    UnexpectedShape {
+        path: String,
+        expected: &'static str,
+        found: String,
+    },
+    // An enum-like integer at `path` has no known meaning.
+    UnknownTag {
+        path: String,
+        tag: i128,
+    },
+    // The server answered [0].
+    WrongRequest,
+}
+
+impl ProtocolError {
+    pub fn shape(expected: &'static str, found: impl Display) -> ProtocolError {
+        ProtocolError::UnexpectedShape {
+            path: String::new(),
+            expected,
+            found: found.to_string(),
+        }
+    }
+
+    pub fn tag(tag: i128) -> ProtocolError {
+        ProtocolError::UnknownTag {
+            path: String::new(),
+            tag,
+        }
+    }
+
+    // Prepends `field` to the path of the error.
+    pub fn within(self, field: &str) -> ProtocolError {
+        if field.is_empty() {
+            return self;
+        }
+        let join = |path: String| {
+            if path.is_empty() {
+                field.to_string()
+            } else if path.starts_with('[') {
+                format!("{}{}", field, path)
+            } else {
+                format!("{}.{}", field, path)
+            }
+        };
+        match self {
+            ProtocolError::UnexpectedShape {
+                path,
+                expected,
+                found,
+            } => ProtocolError::UnexpectedShape {
+                path: join(path),
+                expected,
+                found,
+            },
+            ProtocolError::UnknownTag { path, tag } => ProtocolError::UnknownTag {
+                path: join(path),
+                tag,
+            },
+            e => e,
+        }
+    }
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let root = |path: &str| if path.is_empty() { "value" } else { path }.to_string();
+        match self {
+            ProtocolError::BadModulation(e) => write!(f, "bad modulation: {}", e),
+            ProtocolError::UnexpectedShape {
+                path,
+                expected,
+                found,
+            } => write!(f, "{}: expected {}, found {}", root(path), expected, found),
+            ProtocolError::UnknownTag { path, tag } => {
+                write!(f, "{}: unknown tag {}", root(path), tag)
+            }
+            ProtocolError::WrongRequest => write!(f, "the server rejected the request"),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<DemodulateError> for ProtocolError {
+    fn from(e: DemodulateError) -> ProtocolError {
+        ProtocolError::BadModulation(e)
+    }
+}
+
+pub trait ToValue {
+    fn to_value(&self) -> Value;
+}
+
+// Implementations read through ValueView so that they work on both a &Value
+// and a ValueRef into an Arena.
+pub trait FromValue: Sized {
+    fn from_view<'a, V: ValueView<'a>>(val: V) -> Result<Self, ProtocolError>;
+
+    fn from_value(val: &Value) -> Result<Self, ProtocolError> {
+        Self::from_view(val)
+    }
+}
+
+// The elements of a proper list, without copying them.
+pub fn list_items<'a, V: ValueView<'a>>(val: V) -> Option<Vec<V>> {
+    let mut items = vec![];
+    let mut val = val;
+    loop {
+        match val.view() {
+            View::Cons(car, cdr) => {
+                items.push(car);
+                val = cdr;
+            }
+            View::Nil => return Some(items),
+            _ => return None,
+        }
+    }
+}
+
+fn as_int<'a>(val: impl ValueView<'a>) -> Option<i128> {
+    match val.view() {
+        View::Int(n) => Some(n),
+        _ => None,
+    }
+}
+
+macro_rules! int_impls {
+    ($($t:ty),*) => {$(
+        impl ToValue for $t {
+            fn to_value(&self) -> Value {
+                Value::Int(*self as i128)
+            }
+        }
+
+        // Fails on values out of the range of the type instead of wrapping
+        // around.
+        impl FromValue for $t {
+            fn from_view<'a, V: ValueView<'a>>(val: V) -> Result<$t, ProtocolError> {
+                as_int(val)
+                    .and_then(|n| <$t>::try_from(n).ok())
+                    .ok_or_else(|| ProtocolError::shape(stringify!($t), val))
+            }
+        }
+    )*};
+}
+
+int_impls!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, usize);
+
+impl ToValue for u128 {
+    fn to_value(&self) -> Value {
+        Value::from_big_int(BigInt::from(*self))
+    }
+}
+
+impl FromValue for u128 {
+    fn from_view<'a, V: ValueView<'a>>(val: V) -> Result<u128, ProtocolError> {
+        BigInt::from_view(val)
+            .ok()
+            .and_then(|n| u128::try_from(n).ok())
+            .ok_or_else(|| ProtocolError::shape("u128", val))
+    }
+}
+
+impl ToValue for BigInt {
+    fn to_value(&self) -> Value {
+        Value::from_big_int(self.clone())
+    }
+}
+
+impl FromValue for BigInt {
+    fn from_view<'a, V: ValueView<'a>>(val: V) -> Result<BigInt, ProtocolError> {
+        match val.view() {
+            View::Int(n) => Ok(BigInt::from(n)),
+            View::BigInt(n) => Ok(n.clone()),
+            _ => Err(ProtocolError::shape("an integer", val)),
+        }
+    }
+}
+
+// Flags are 0 or 1, like the server's.
+impl ToValue for bool {
+    fn to_value(&self) -> Value {
+        Value::Int(*self as i128)
+    }
+}
+
+impl FromValue for bool {
+    fn from_view<'a, V: ValueView<'a>>(val: V) -> Result<bool, ProtocolError> {
+        match as_int(val) {
+            Some(0) => Ok(false),
+            Some(1) => Ok(true),
+            _ => Err(ProtocolError::shape("0 or 1", val)),
+        }
+    }
+}
+
+impl ToValue for Value {
+    fn to_value(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl FromValue for Value {
+    fn from_view<'a, V: ValueView<'a>>(val: V) -> Result<Value, ProtocolError> {
+        Ok(val.to_owned_value())
+    }
+}
+
+impl<T: ToValue + ?Sized> ToValue for &T {
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+}
+
+impl<T: ToValue> ToValue for Box<T> {
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+}
+
+impl<T: FromValue> FromValue for Box<T> {
+    fn from_view<'a, V: ValueView<'a>>(val: V) -> Result<Box<T>, ProtocolError> {
+        T::from_view(val).map(Box::new)
+    }
+}
+
+impl<T: ToValue> ToValue for [T] {
+    fn to_value(&self) -> Value {
+        from_vec(self.iter().map(T::to_value).collect())
+    }
+}
+
+impl<T: ToValue> ToValue for Vec<T> {
+    fn to_value(&self) -> Value {
+        self.as_slice().to_value()
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_view<'a, V: ValueView<'a>>(val: V) -> Result<Vec<T>, ProtocolError> {
+        list_items(val)
+            .ok_or_else(|| ProtocolError::shape("a list", val))?
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| T::from_view(v).map_err(|e| e.within(&format!("[{}]", i))))
+            .collect()
+    }
+}
+
+// None is nil. This is ambiguous for types that can be nil themselves, such
+// as Vec, which the protocol never wraps in an option.
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self) -> Value {
+        match self {
+            Some(v) => v.to_value(),
+            None => Value::Nil,
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_view<'a, V: ValueView<'a>>(val: V) -> Result<Option<T>, ProtocolError> {
+        match val.view() {
+            View::Nil => Ok(None),
+            _ => T::from_view(val).map(Some),
+        }
+    }
+}
+
+// Tuples are lists, not nested pairs. Use a struct with a manual impl for a
+// pair such as (x . y).
+macro_rules! tuple_impls {
+    ($($expected:literal => ($($t:ident $i:tt),*);)*) => {$(
+        impl<$($t: ToValue),*> ToValue for ($($t,)*) {
+            fn to_value(&self) -> Value {
+                from_vec(vec![$(self.$i.to_value()),*])
+            }
+        }
+
+        impl<$($t: FromValue),*> FromValue for ($($t,)*) {
+            fn from_view<'a, V: ValueView<'a>>(val: V) -> Result<($($t,)*), ProtocolError> {
+                let items = list_items(val)
+                    .filter(|items| items.len() == [$($i),*].len())
+                    .ok_or_else(|| ProtocolError::shape($expected, val))?;
+                Ok(($(
+                    $t::from_view(items[$i])
+                        .map_err(|e| e.within(concat!("[", stringify!($i), "]")))?,
+                )*))
+            }
+        }
+    )*};
+}
+
+tuple_impls! {
+    "a list of 2" => (A 0, B 1);
+    "a list of 3" => (A 0, B 1, C 2);
+    "a list of 4" => (A 0, B 1, C 2, D 3);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl::*;
+    use rust_game_base_derive::{FromValue, ToValue};
+
+    #[derive(Clone, Debug, Eq, PartialEq, ToValue, FromValue)]
+    #[value(tag = 7)]
+    struct Tagged {
+        flag: bool,
+        items: Vec<(i8, Option<u64>)>,
+    }
+
+    #[derive(Clone, Copy, Debug, Eq, PartialEq, ToValue, FromValue)]
+    enum Unit {
+        A,
+        #[value(tag = 5)]
+        B,
+    }
+
+    #[derive(Clone, Debug, Eq, PartialEq, ToValue, FromValue)]
+    enum Mixed {
+        Pair(Unit, #[value(name = "n")] i32),
+        #[value(tag = 9)]
+        Named {
+            inner: Tagged,
+        },
+        Empty,
+    }
+
+    // Sent as twice the number.
+    mod doubled {
+        use super::*;
+
+        pub fn to_value(n: &i32) -> Value {
+            (n * 2).to_value()
+        }
+
+        pub fn from_view<'a, V: ValueView<'a>>(val: V) -> Result<i32, ProtocolError> {
+            Ok(i32::from_view(val)? / 2)
+        }
+    }
+
+    #[derive(Clone, Debug, Eq, PartialEq, ToValue, FromValue)]
+    struct Doubled {
+        #[value(with = "doubled")]
+        n: i32,
+    }
+
+    fn round_trip<T: ToValue + FromValue>(v: &T) -> T {
+        let val = demodulate_from_string(&modulate_to_string(&v.to_value())).unwrap();
+        T::from_value(&val).unwrap()
+    }
+
+    #[test]
+    fn test_primitives() {
+        assert_eq!(round_trip(&-5_i8), -5);
+        assert_eq!(round_trip(&u128::MAX), u128::MAX);
+        assert!(round_trip(&true));
+        assert_eq!(round_trip(&vec![1_usize, 2, 3]), vec![1, 2, 3]);
+        assert_eq!(round_trip(&Some(3_u8)), Some(3));
+        assert_eq!(round_trip(&None::<u8>), None);
+        assert_eq!(round_trip(&(1_u8, -1_i64, 2_u16)), (1, -1, 2));
+        assert_eq!(
+            (1_u8, vec![true]).to_value().to_string(),
+            "(1 (1))".to_string()
+        );
+
+        assert_eq!(
+            u8::from_value(&int(256)).unwrap_err().to_string(),
+            "value: expected u8, found 256"
+        );
+        assert_eq!(
+            bool::from_value(&int(2)).unwrap_err().to_string(),
+            "value: expected 0 or 1, found 2"
+        );
+        assert_eq!(
+            Vec::<i32>::from_value(&list!(int(1), nil()))
+                .unwrap_err()
+                .to_string(),
+            "[1]: expected i32, found nil"
+        );
+        assert_eq!(
+            <(i32, i32)>::from_value(&list!(int(1)))
+                .unwrap_err()
+                .to_string(),
+            "value: expected a list of 2, found (1)"
+        );
+    }
+
+    #[test]
+    fn test_derive() {
+        let tagged = Tagged {
+            flag: true,
+            items: vec![(1, None), (-1, Some(2))],
+        };
+        assert_eq!(tagged.to_value().to_string(), "(7 1 ((1 nil) (-1 2)))");
+        assert_eq!(round_trip(&tagged), tagged);
+        assert_eq!(Unit::B.to_value().to_string(), "5");
+        for mixed in &[
+            Mixed::Pair(Unit::B, -3),
+            Mixed::Named { inner: tagged },
+            Mixed::Empty,
+        ] {
+            assert_eq!(&round_trip(mixed), mixed);
+        }
+        assert_eq!(Mixed::Empty.to_value().to_string(), "(2)");
+
+        let err = |val: Value| Mixed::from_value(&val).unwrap_err().to_string();
+        assert_eq!(
+            err(list!(
+                int(9),
+                list!(int(7), int(1), list!(list!(int(1), int(2), int(3))))
+            )),
+            "inner.items[0]: expected a list of 2, found (1 2 3)"
+        );
+        assert_eq!(
+            err(list!(int(9), list!(int(6), int(1), nil()))),
+            "inner: unknown tag 6"
+        );
+        assert_eq!(err(list!(int(0), int(1), int(2))), "[1]: unknown tag 1");
+        assert_eq!(
+            err(list!(int(0), int(0))),
+            "value: expected [0, _0, n], found (0 0)"
+        );
+        assert_eq!(err(int(0)), "value: expected [tag, ...], found 0");
+        assert_eq!(err(list!(int(4))), "value: unknown tag 4");
+        assert_eq!(
+            err(list!(int(0), int(5), nil())),
+            "n: expected i32, found nil"
+        );
+
+        let doubled = Doubled { n: 3 };
+        assert_eq!(doubled.to_value().to_string(), "(6)");
+        assert_eq!(round_trip(&doubled), doubled);
+        assert_eq!(
+            Doubled::from_value(&list!(nil())).unwrap_err().to_string(),
+            "n: expected i32, found nil"
+        );
+        assert_eq!(
+            Doubled::from_value(&int(6)).unwrap_err().to_string(),
+            "value: expected a list, found 6"
+        );
+    }
+}
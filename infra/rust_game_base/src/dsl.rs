@@ -13,6 +13,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::arena::ValueView;
+use crate::convert::ProtocolError;
 use crate::value::Value;
 use std::convert::TryInto;
 
@@ -28,8 +30,280 @@ pub fn nil() -> Value {
     Value::Nil
 }
 
+// list![a, b] is (a b) and list![a, b; tail] is (a b . tail).
+#[macro_export]
 macro_rules! list {
-    () => { nil() };
-    ($x:expr) => { cons($x, nil()) };
-    ($x:expr, $($xs:expr),*) => { cons($x, list!($($xs),*)) }
+    () => { $crate::dsl::nil() };
+    ($x:expr; $tail:expr) => { $crate::dsl::cons($x, $tail) };
+    ($x:expr, $($xs:expr),+; $tail:expr) => { $crate::dsl::cons($x, list!($($xs),+; $tail)) };
+    ($x:expr) => { $crate::dsl::cons($x, $crate::dsl::nil()) };
+    ($x:expr, $($xs:expr),*) => { $crate::dsl::cons($x, list!($($xs),*)) }
+}
+
+// Patterns for destructure! and value_match!:
+//
+//   _          anything
+//   nil        nil
+//   0, -1      that integer
+//   x          binds the value itself, as a ValueView
+//   x: T       binds T::from_view(value), e.g. `turn: usize` or `p: Param`
+//   [p, q]     a list of exactly these elements
+//   [p, q; r]  p and q followed by the rest r, e.g. [x: isize; y: isize] is (x . y)
+//   (p)        p. Types with commas need parentheses, e.g. (v: (Vec<(i32, i32)>))
+
+// Binds `$v: impl ValueView` to a pattern, or returns the mismatch converted
+// with From.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __value_pattern {
+    (@fail $mode:ident, $e:expr) => { return Err(::std::convert::From::from($e)) };
+    (@mismatch $mode:ident, $v:ident, $path:expr, $expected:expr) => {
+        $crate::__value_pattern!(
+            @fail $mode,
+            $crate::convert::ProtocolError::shape($expected, $v).within(&$path)
+        )
+    };
+
+    ($mode:ident, $v:ident, $path:expr, _) => {};
+    ($mode:ident, $v:ident, $path:expr, nil) => {
+        match $crate::arena::ValueView::view($v) {
+            $crate::arena::View::Nil => {}
+            _ => $crate::__value_pattern!(@mismatch $mode, $v, $path, "nil"),
+        }
+    };
+    ($mode:ident, $v:ident, $path:expr, $lit:literal) => {
+        match $crate::arena::ValueView::view($v) {
+            $crate::arena::View::Int(n) if n == $lit => {}
+            _ => $crate::__value_pattern!(@mismatch $mode, $v, $path, stringify!($lit)),
+        }
+    };
+    ($mode:ident, $v:ident, $path:expr, ($($p:tt)+)) => {
+        $crate::__value_pattern!($mode, $v, $path, $($p)+);
+    };
+    ($mode:ident, $v:ident, $path:expr, [$($p:tt)*]) => {
+        let rest = $v;
+        $crate::__value_pattern!(
+            @list $mode, $v, rest, $path, 0, concat!("[", stringify!($($p)*), "]"), [] $($p)*
+        );
+    };
+    ($mode:ident, $v:ident, $path:expr, $name:ident : $t:ty) => {
+        let $name = match <$t as $crate::convert::FromValue>::from_view($v) {
+            Ok(x) => x,
+            Err(e) => $crate::__value_pattern!(@fail $mode, e.within(&$path)),
+        };
+    };
+    ($mode:ident, $v:ident, $path:expr, $name:ident) => {
+        let $name = $v;
+    };
+
+    // Splits the elements of a list pattern at the top-level commas,
+    // collecting the tokens of the current element in [..].
+    (@list $mode:ident, $whole:ident, $rest:ident, $path:expr, $i:expr, $expected:expr, []) => {
+        match $crate::arena::ValueView::view($rest) {
+            $crate::arena::View::Nil => {}
+            _ => $crate::__value_pattern!(@mismatch $mode, $whole, $path, $expected),
+        }
+    };
+    (@list $mode:ident, $whole:ident, $rest:ident, $path:expr, $i:expr, $expected:expr,
+     [$($cur:tt)+]) => {
+        $crate::__value_pattern!(@elem $mode, $whole, $rest, $path, $i, $expected, $($cur)+);
+        $crate::__value_pattern!(@list $mode, $whole, $rest, $path, $i + 1, $expected, []);
+    };
+    (@list $mode:ident, $whole:ident, $rest:ident, $path:expr, $i:expr, $expected:expr,
+     [$($cur:tt)+] , $($more:tt)*) => {
+        $crate::__value_pattern!(@elem $mode, $whole, $rest, $path, $i, $expected, $($cur)+);
+        $crate::__value_pattern!(
+            @list $mode, $whole, $rest, $path, $i + 1, $expected, [] $($more)*
+        );
+    };
+    (@list $mode:ident, $whole:ident, $rest:ident, $path:expr, $i:expr, $expected:expr,
+     [$($cur:tt)+] ; $($tail:tt)+) => {
+        $crate::__value_pattern!(@elem $mode, $whole, $rest, $path, $i, $expected, $($cur)+);
+        $crate::__value_pattern!(
+            $mode, $rest, format!("{}[{}..]", $path, $i + 1), $($tail)+
+        );
+    };
+    (@list $mode:ident, $whole:ident, $rest:ident, $path:expr, $i:expr, $expected:expr,
+     [$($cur:tt)*] $next:tt $($more:tt)*) => {
+        $crate::__value_pattern!(
+            @list $mode, $whole, $rest, $path, $i, $expected, [$($cur)* $next] $($more)*
+        );
+    };
+    (@elem $mode:ident, $whole:ident, $rest:ident, $path:expr, $i:expr, $expected:expr,
+     $($p:tt)+) => {
+        let (car, cdr) = match $crate::arena::ValueView::view($rest) {
+            $crate::arena::View::Cons(car, cdr) => (car, cdr),
+            _ => $crate::__value_pattern!(@mismatch $mode, $whole, $path, $expected),
+        };
+        $crate::__value_pattern!($mode, car, format!("{}[{}]", $path, $i), $($p)+);
+        let $rest = cdr;
+    };
+}
+
+// Binds the names in a pattern, or returns the mismatch from the enclosing
+// function, converted with From:
+//
+//   destructure!(let [1, state: CurrentGameState, _, _] = &val);
+#[macro_export]
+macro_rules! destructure {
+    (let $pat:tt = $val:expr) => {
+        let v = $val;
+        $crate::__value_pattern!(destructure, v, "", $pat);
+    };
+}
+
+// Evaluates to Ok(body) for the first arm that matches, or to the mismatch of
+// the last arm:
+//
+//   value_match!(&val, {
+//       [0] => None,
+//       [1, _, _, state: CurrentState] => Some(state),
+//   })
+#[macro_export]
+macro_rules! value_match {
+    ($val:expr, { $($pat:tt => $body:expr),+ $(,)? }) => {{
+        let v = $val;
+        $crate::__value_match!(v, $($pat => $body),+)
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __value_match {
+    ($v:ident, $pat:tt => $body:expr $(, $pats:tt => $bodies:expr)*) => {
+        match $crate::dsl::__check($v, |v| {
+            $crate::__value_pattern!(destructure, v, "", $pat);
+            Ok($crate::__value_names!($pat))
+        }) {
+            Ok($crate::__value_names!($pat)) => Ok($body),
+            Err(e) => $crate::__value_match!(@next e, $v, $($pats => $bodies),*),
+        }
+    };
+    (@next $e:ident, $v:ident, ) => { Err($e) };
+    (@next $e:ident, $v:ident, $($arms:tt)+) => { $crate::__value_match!($v, $($arms)+) };
+}
+
+// The names a pattern binds, as nested tuples: [a, _, b: T; c] is
+// (a, ((), (b, c))). value_match! returns them from the closure that
+// converted the value and binds them again in the arm.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __value_names {
+    (@list []) => { () };
+    (@list [$($cur:tt)+]) => { ($crate::__value_names!($($cur)+), ()) };
+    (@list [$($cur:tt)+] , $($more:tt)*) => {
+        ($crate::__value_names!($($cur)+), $crate::__value_names!(@list [] $($more)*))
+    };
+    (@list [$($cur:tt)+] ; $($tail:tt)+) => {
+        ($crate::__value_names!($($cur)+), $crate::__value_names!($($tail)+))
+    };
+    (@list [$($cur:tt)*] $next:tt $($more:tt)*) => {
+        $crate::__value_names!(@list [$($cur)* $next] $($more)*)
+    };
+
+    (_) => { () };
+    (nil) => { () };
+    ($lit:literal) => { () };
+    (($($p:tt)+)) => { $crate::__value_names!($($p)+) };
+    ([$($p:tt)*]) => { $crate::__value_names!(@list [] $($p)*) };
+    ($name:ident : $t:ty) => { $name };
+    ($name:ident) => { $name };
+}
+
+// Gives the closures in value_match! a parameter type.
+#[doc(hidden)]
+pub fn __check<'a, V: ValueView<'a>, T>(
+    v: V,
+    f: impl FnOnce(V) -> Result<T, ProtocolError>,
+) -> Result<T, ProtocolError> {
+    f(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_list_tail() {
+        assert_eq!(list!(int(1); int(2)).to_string(), "(1 . 2)");
+        assert_eq!(list!(int(1), int(2); list!(int(3))).to_string(), "(1 2 3)");
+    }
+
+    fn parse(val: &Value) -> Result<(i128, Point, Vec<isize>), ProtocolError> {
+        destructure!(let [tag: i128, [x: isize; y: isize], _; rest: (Vec<isize>)] = val);
+        Ok((tag, Point::new(x, y), rest))
+    }
+
+    #[test]
+    fn test_destructure() {
+        let val = list!(int(3), cons(int(1), int(-2)), nil(), int(4), int(5));
+        assert_eq!(parse(&val).unwrap(), (3, Point::new(1, -2), vec![4, 5]));
+        let err = |val: Value| parse(&val).unwrap_err().to_string();
+        assert_eq!(
+            err(list!(int(3), cons(int(1), nil()))),
+            "[1][1..]: expected isize, found nil"
+        );
+        // The expected shape is the pattern, spaced as stringify! likes.
+        let short = err(list!(int(3)));
+        assert!(short.starts_with("value: expected [tag"), "{}", short);
+        assert!(short.ends_with("], found (3)"), "{}", short);
+        assert_eq!(
+            err(list!(int(3), cons(int(1), int(2)), nil(), nil())),
+            "[3..][0]: expected isize, found nil"
+        );
+    }
+
+    #[test]
+    fn test_value_match() {
+        let classify = |val: &Value| {
+            value_match!(val, {
+                [0] => "rejected".to_string(),
+                [1, nil, state: CurrentGameState] => format!("{:?}", state),
+                [1, rest] => format!("one {}", rest),
+                (n: i128) => format!("int {}", n),
+            })
+        };
+        assert_eq!(classify(&list!(int(0))).unwrap(), "rejected");
+        assert_eq!(classify(&list!(int(1), nil(), int(2))).unwrap(), "END");
+        assert_eq!(classify(&list!(int(1), int(2))).unwrap(), "one 2");
+        assert_eq!(classify(&int(-7)).unwrap(), "int -7");
+        assert_eq!(
+            classify(&list!(int(2))).unwrap_err().to_string(),
+            "value: expected i128, found (2)"
+        );
+        let unmatched = value_match!(&list!(int(1), nil(), int(5)), {
+            [_, _, s: CurrentGameState] => s,
+        });
+        assert_eq!(
+            unmatched.unwrap_err(),
+            ProtocolError::UnknownTag {
+                path: "[2]".to_string(),
+                tag: 5
+            }
+        );
+    }
+
+    static CONVERSIONS: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug)]
+    struct Counted(i128);
+
+    impl crate::convert::FromValue for Counted {
+        fn from_view<'a, V: ValueView<'a>>(val: V) -> Result<Self, ProtocolError> {
+            CONVERSIONS.fetch_add(1, Ordering::SeqCst);
+            i128::from_view(val).map(Counted)
+        }
+    }
+
+    #[test]
+    fn test_value_match_converts_once() {
+        let val = list!(int(1), int(2));
+        let sum = value_match!(&val, {
+            [a: Counted, b: Counted] => a.0 + b.0,
+        });
+        assert_eq!(sum.unwrap(), 3);
+        assert_eq!(CONVERSIONS.load(Ordering::SeqCst), 2);
+    }
 }
@@ -13,260 +13,533 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::arena::*;
+use crate::convert::*;
 use crate::game::*;
+use crate::replay::Recorder;
+use crate::rules::*;
+use crate::transport::*;
 use crate::value::*;
-use anyhow::{bail, Context, Result};
-use std::io::{self, Write};
+use anyhow::{bail, Result};
+use std::cell::RefCell;
 
+const CREATE_REQUEST_TAG: i128 = 1;
 const JOIN_REQUEST_TAG: i128 = 2;
 const START_REQUEST_TAG: i128 = 3;
 const COMMAND_REQUEST_TAG: i128 = 4;
 
-fn get_player_key() -> i128 {
-    std::env::args().nth(1).unwrap().parse().unwrap()
+// A connection to the game server for one player.
+pub struct GameClient {
+    player_key: i128,
+    transport: Box<dyn Transport>,
+    // The latest response from the server, used to validate commands.
+    last_response: Option<Response>,
+    // Reused for every response.
+    arena: Arena,
 }
 
-pub fn send_join_request() -> Result<Response> {
-    use crate::dsl::*;
-    let player_key = get_player_key();
-    eprintln!("send: JOIN player_key={}", player_key);
-    send_and_receive_game_state(&list!(
-        int(JOIN_REQUEST_TAG),
-        int(player_key),
-        list!(int(192496425430_i64), int(103652820))
-    ))
-}
+impl GameClient {
+    pub fn new(player_key: i128, transport: Box<dyn Transport>) -> GameClient {
+        GameClient {
+            player_key,
+            transport,
+            last_response: None,
+            arena: Arena::new(),
+        }
+    }
 
-pub fn send_start_request(params: &Param) -> Result<Response> {
-    use crate::dsl::*;
-    let player_key = get_player_key();
-    let is_tutorial: bool = std::env::vars().any(|(key, _)| key == "TUTORIAL_MODE");
-
-    if is_tutorial {
-        eprintln!("send: START player_key={} tutorial", player_key);
-    } else {
-        eprintln!("send: START player_key={} {:?}", player_key, params);
+    // `bot PLAYER_KEY` talks through stdin/stdout (infra/interact/interact.py),
+    // and `bot SERVER_URL PLAYER_KEY` talks to the server directly.
+    // With REPLAY_FILE set, the match is recorded there (see replay.rs).
+    pub fn from_args() -> Result<GameClient> {
+        let args = std::env::args().skip(1).collect::<Vec<_>>();
+        let (player_key, transport): (_, Box<dyn Transport>) = match args.as_slice() {
+            [player_key] => (player_key.parse()?, Box::new(StdioTransport)),
+            [server_url, player_key, ..] => (
+                player_key.parse()?,
+                Box::new(HttpTransport::new(server_url)?),
+            ),
+            [] => bail!("usage: bot [SERVER_URL] PLAYER_KEY"),
+        };
+        let transport = match std::env::var_os("REPLAY_FILE") {
+            Some(path) => Box::new(Recorder::create(transport, path)?),
+            None => transport,
+        };
+        Ok(GameClient::new(player_key, transport))
     }
 
-    let params = if is_tutorial {
-        list!()
-    } else {
-        list!(
-            int(params.energy),
-            int(params.laser_power),
-            int(params.cool_down_per_turn),
-            int(params.life)
-        )
-    };
-    send_and_receive_game_state(&list!(int(START_REQUEST_TAG), int(player_key), params))
-}
+    pub fn player_key(&self) -> i128 {
+        self.player_key
+    }
 
-pub fn send_command_request(it: &mut impl Iterator<Item = Command>) -> Result<Response> {
-    use crate::dsl::*;
-    let player_key = get_player_key();
-    let commands = it.collect::<Vec<Command>>();
-    eprintln!("send: COMMAND player_key={} {:?}", player_key, commands);
-    let commands = commands
-        .iter()
-        .fold(nil(), |acc, x| cons(x.to_value(), acc));
-    send_and_receive_game_state(&list!(int(COMMAND_REQUEST_TAG), int(player_key), commands))
-}
+    pub fn last_response(&self) -> Option<&Response> {
+        self.last_response.as_ref()
+    }
+
+    pub fn join(&mut self) -> Result<Response> {
+        use crate::dsl::*;
+        eprintln!("send: JOIN player_key={}", self.player_key);
+        self.send_and_receive_game_state(&list!(
+            int(JOIN_REQUEST_TAG),
+            int(self.player_key),
+            list!(int(192496425430_i64), int(103652820))
+        ))
+    }
+
+    pub fn start(&mut self, params: &Param) -> Result<Response> {
+        use crate::dsl::*;
+        let is_tutorial: bool = std::env::vars().any(|(key, _)| key == "TUTORIAL_MODE");
 
-fn parse_current_game_state(val: &Value) -> CurrentGameState {
-    match *val {
-        Value::Int(0) => CurrentGameState::START,
-        Value::Int(1) => CurrentGameState::PLAYING,
-        Value::Int(2) => CurrentGameState::END,
-        _ => panic!(),
+        if is_tutorial {
+            eprintln!("send: START player_key={} tutorial", self.player_key);
+        } else {
+            eprintln!("send: START player_key={} {:?}", self.player_key, params);
+        }
+
+        let params = if is_tutorial {
+            list!()
+        } else {
+            params.to_value()
+        };
+        self.send_and_receive_game_state(&list!(
+            int(START_REQUEST_TAG),
+            int(self.player_key),
+            params
+        ))
+    }
+
+    pub fn command(&mut self, it: &mut impl Iterator<Item = Command>) -> Result<Response> {
+        use crate::dsl::*;
+        let commands = self.strip_invalid_commands(it.collect::<Vec<Command>>());
+        eprintln!(
+            "send: COMMAND player_key={} {:?}",
+            self.player_key, commands
+        );
+        let commands = commands
+            .iter()
+            .fold(nil(), |acc, x| cons(x.to_value(), acc));
+        self.send_and_receive_game_state(&list!(
+            int(COMMAND_REQUEST_TAG),
+            int(self.player_key),
+            commands
+        ))
+    }
+
+    // Drops the commands which are illegal in the latest known state.
+    fn strip_invalid_commands(&self, commands: Vec<Command>) -> Vec<Command> {
+        let (stage, state) = match &self.last_response {
+            Some(Response {
+                stage_data,
+                current_state: Some(state),
+                ..
+            }) => (stage_data, state),
+            _ => return commands,
+        };
+        match validate_commands(stage, state, stage.self_role, &commands) {
+            Ok(()) => commands,
+            Err(errors) => {
+                for e in &errors {
+                    eprintln!("drop: {:?} ({:?})", e.command, e.kind);
+                }
+                commands
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(i, _)| errors.iter().all(|e| e.index != *i))
+                    .map(|(_, c)| c)
+                    .collect()
+            }
+        }
+    }
+
+    fn send_and_receive_game_state(&mut self, val: &Value) -> Result<Response> {
+        eprintln!("{:?}", val);
+        let resp = self.transport.send(&modulate_to_string(val))?;
+        let resp = parse_response_from(self.arena.demodulate(&resp)?)?;
+        eprintln!("recieve: {:#?}", resp);
+        self.last_response = Some(resp.clone());
+        Ok(resp)
     }
 }
 
-fn parse_obstacle(val: Value) -> Result<Option<Obstacle>> {
-    Ok(if let Some(val) = to_option(val) {
-        Some(match to_vec(val.clone())?.as_slice() {
-            [gravity_radius, stage_half_size] => Obstacle {
-                gravity_radius: to_int(gravity_radius)? as usize,
-                stage_half_size: to_int(stage_half_size)? as usize,
-            },
-            _ => bail!("unexpected value: {}", val.to_string()),
-        })
-    } else {
-        None
-    })
+thread_local! {
+    // The client behind send_*_request, created from the command line.
+    static DEFAULT_CLIENT: RefCell<Option<GameClient>> = RefCell::default();
 }
 
-fn parse_initialize_param(val: Value) -> Result<InitializeParam> {
-    Ok(match to_vec(val.clone())?.as_slice() {
-        [total_cost, thrust_limit, heat_limit] => InitializeParam {
-            total_cost: to_int(total_cost)? as usize,
-            thrust_limit: to_int(thrust_limit)? as usize,
-            heat_limit: to_int(heat_limit)? as usize,
-        },
-        _ => bail!("unexpected initialize param: {}", val.to_string()),
+fn with_default_client<T>(f: impl FnOnce(&mut GameClient) -> Result<T>) -> Result<T> {
+    DEFAULT_CLIENT.with(|client| {
+        let mut client = client.borrow_mut();
+        if client.is_none() {
+            *client = Some(GameClient::from_args()?);
+        }
+        f(client.as_mut().unwrap())
     })
 }
 
-fn parse_stage_data(val: Value) -> Result<StageData> {
-    Ok(match to_vec(val.clone())?.as_slice() {
-        [total_turns, role, initialize_param, obstacle, defender] => StageData {
-            total_turns: to_int(total_turns)? as usize,
-            self_role: parse_role(role.clone())?,
-            initialize_param: parse_initialize_param(initialize_param.clone())?,
-            obstacle: parse_obstacle(obstacle.clone())?,
-            defender: match defender {
-                Value::Nil => None,
-                _ => Some(parse_params(defender.clone())?),
-            },
-        },
-        _ => bail!("unexpected value: {}", val.to_string()),
-    })
+pub fn send_join_request() -> Result<Response> {
+    with_default_client(|client| client.join())
 }
 
-fn parse_position(val: Value) -> Result<(isize, isize)> {
-    Ok(match val {
-        Value::Cons(x, y) => (to_int(&*x)? as isize, to_int(&*y)? as isize),
-        _ => bail!("Unexpected value: ".to_string() + &val.to_string()),
-    })
+pub fn send_start_request(params: &Param) -> Result<Response> {
+    with_default_client(|client| client.start(params))
 }
 
-fn parse_point(val: Value) -> Result<Point> {
-    Ok(match val {
-        Value::Cons(x, y) => Point {
-            x: to_int(&*x)? as isize,
-            y: to_int(&*y)? as isize,
-        },
-        _ => bail!("Unexpected value: ".to_string() + &val.to_string()),
-    })
+pub fn send_command_request(it: &mut impl Iterator<Item = Command>) -> Result<Response> {
+    with_default_client(|client| client.command(it))
 }
 
-fn parse_role(val: Value) -> Result<Role> {
-    Ok(match to_int(&val)? {
-        0 => Role::ATTACKER,
-        1 => Role::DEFENDER,
-        _ => bail!("unexpected value: {}", val.to_string()),
-    })
+pub fn parse_response(val: Value) -> Result<Response, ProtocolError> {
+    parse_response_from(&val)
 }
 
-fn parse_params(val: Value) -> Result<Param> {
-    Ok(match to_vec(val.clone())?.as_slice() {
-        [energy, laser_power, cool_down_per_turn, life] => Param {
-            energy: to_int(energy)? as usize,
-            laser_power: to_int(laser_power)? as usize,
-            cool_down_per_turn: to_int(cool_down_per_turn)? as usize,
-            life: to_int(life)? as usize,
-        },
-        _ => bail!("unexpected value: {}", val.to_string()),
-    })
+// Also takes a ValueRef, to parse without building a Value.
+pub fn parse_response_from<'a>(val: impl ValueView<'a>) -> Result<Response, ProtocolError> {
+    value_match!(val, {
+        [0] => Err(ProtocolError::WrongRequest),
+        _ => Response::from_view(val),
+    })?
 }
 
-fn parse_machine(val: Value) -> Result<Machine> {
-    Ok(match to_vec(val.clone())?.as_slice() {
-        [role, machine_id, position, velocity, params, heat, heat_limit, move_limit] => Machine {
-            role: parse_role(role.clone())?,
-            machine_id: to_int(machine_id)? as isize,
-            position: parse_point(position.clone())?,
-            velocity: parse_point(velocity.clone())?,
-            params: parse_params(params.clone())?,
-            heat: to_int(heat)? as usize,
-            heat_limit: to_int(heat_limit)? as usize,
-            move_limit: to_int(move_limit)? as usize,
+pub fn parse_request(val: Value) -> Result<Request, ProtocolError> {
+    parse_request_from(&val)
+}
+
+pub fn parse_request_from<'a>(val: impl ValueView<'a>) -> Result<Request, ProtocolError> {
+    let vals = list_items(val).ok_or_else(|| ProtocolError::shape("a list", val))?;
+    let (tag, rest) = match vals.split_first() {
+        Some((tag, rest)) => (i128::from_view(*tag)?, rest),
+        None => return Err(ProtocolError::shape("[tag, ...]", val)),
+    };
+    let within = |field| move |e: ProtocolError| e.within(field);
+    Ok(match (tag, rest) {
+        (CREATE_REQUEST_TAG, [_]) => Request::Create,
+        (JOIN_REQUEST_TAG, [player_key, _]) => Request::Join {
+            player_key: FromValue::from_view(*player_key).map_err(within("player_key"))?,
+        },
+        (START_REQUEST_TAG, [player_key, params]) => Request::Start {
+            player_key: FromValue::from_view(*player_key).map_err(within("player_key"))?,
+            params: FromValue::from_view(*params).map_err(within("params"))?,
         },
-        _ => bail!("unexpected value: ".to_string() + &val.to_string()),
+        (COMMAND_REQUEST_TAG, [player_key, commands]) => Request::Command {
+            player_key: FromValue::from_view(*player_key).map_err(within("player_key"))?,
+            commands: FromValue::from_view(*commands).map_err(within("commands"))?,
+        },
+        (CREATE_REQUEST_TAG..=COMMAND_REQUEST_TAG, _) => {
+            return Err(ProtocolError::shape("a request", val))
+        }
+        (tag, _) => return Err(ProtocolError::tag(tag)),
     })
 }
 
-fn parse_action_result(val: Value) -> Result<Vec<ActionResult>> {
-    let vals = to_vec(val.clone())?;
-    vals.into_iter()
-        .map(|val| -> Result<ActionResult> {
-            let vals = to_vec(val)?;
-            Ok(match (to_int(&vals[0])?, &vals[1..]) {
-                (0, [a]) => ActionResult::Thruster {
-                    a: parse_point(a.clone())?,
-                },
-                (1, [power, area]) => ActionResult::Bomb {
-                    power: to_int(power)? as usize,
-                    area: to_int(area)? as usize,
-                },
-                (2, [opponent, power, intensity, _3]) => ActionResult::Laser {
-                    opponent: parse_point(opponent.clone())?,
-                    power: to_int(power)? as usize,
-                    intensity: to_int(intensity)? as usize,
-                    _3: to_int(_3)? as isize,
-                },
-                (3, [params]) => ActionResult::Split {
-                    params: parse_params(params.clone())?,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESPONSE: &str = "110110000111011000011111011110000100000000110101111011110001000000000110110000111011100100000000111101110000100001101110100000000011110111001100100110110101011011010101101100001000011110110000111110111000010000110111010000000001111111101100001110101111101100010111110101111111101100001010111101110011001001101101010110110101011011000010011010110111001000000110110000100111111011000101111011100011000001101111110110101011010110110010000000011111101011011000011111011100010111101101111111110100001010111101111000011001000011010110110001011011000010011010110111001000000110110000100110000000000";
+
+    #[test]
+    fn test_parse_request() {
+        use crate::dsl::*;
+        // CREATE in infra/tester/tester.py.
+        let create = demodulate_from_string("11011000011101000").unwrap();
+        assert_eq!(parse_request(create).unwrap(), Request::Create);
+
+        let commands = vec![
+            Command::Thrust(0, Point::new(1, -1)),
+            Command::Bomb(1),
+            Command::Beam(2, Point::new(-30, 40), 16),
+            Command::Split(
+                3,
+                Param {
+                    energy: 10,
+                    laser_power: 0,
+                    cool_down_per_turn: 2,
+                    life: 1,
                 },
-                _ => bail!(
-                    "invalid action result: {:?}",
-                    vals.into_iter().map(|r| r.to_string()).collect::<Vec<_>>()
+            ),
+        ];
+        let val = list!(
+            int(COMMAND_REQUEST_TAG),
+            int(12345),
+            from_vec(commands.iter().map(|c| c.to_value()).collect())
+        );
+        assert_eq!(
+            parse_request(val).unwrap(),
+            Request::Command {
+                player_key: 12345,
+                commands,
+            }
+        );
+
+        let val = list!(int(START_REQUEST_TAG), int(12345), nil());
+        assert_eq!(
+            parse_request(val).unwrap(),
+            Request::Start {
+                player_key: 12345,
+                params: None,
+            }
+        );
+        assert!(parse_request(list!(int(9), int(12345))).is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let resp = demodulate_from_string(RESPONSE).unwrap();
+        let parsed = parse_response(resp.clone()).unwrap();
+        assert_eq!(
+            modulate_to_string(&parsed.to_value()),
+            modulate_to_string(&resp)
+        );
+    }
+
+    #[test]
+    fn test_protocol_errors() {
+        use crate::dsl::*;
+        assert_eq!(
+            parse_response(demodulate_from_string("1101000").unwrap()).unwrap_err(),
+            ProtocolError::WrongRequest
+        );
+        assert_eq!(
+            ProtocolError::from(try_demodulate_from_string("0110").unwrap_err()),
+            ProtocolError::BadModulation(DemodulateError::UnexpectedEnd(4))
+        );
+
+        let stage = parse_response(demodulate_from_string(RESPONSE).unwrap())
+            .unwrap()
+            .stage_data
+            .to_value();
+        let machine = |params| {
+            list!(
+                list!(
+                    int(0),
+                    int(0),
+                    cons(int(1), int(2)),
+                    cons(int(0), int(0)),
+                    params,
+                    int(0),
+                    int(64),
+                    int(1)
                 ),
-            })
-        })
-        .collect::<Result<Vec<_>>>()
-        .with_context(|| format!("invalid action results: {:?}", val.to_string()))
-}
+                nil()
+            )
+        };
+        let params = list!(int(10), int(0), int(0), int(1));
+        let state = list!(
+            int(3),
+            nil(),
+            list!(machine(params.clone()), machine(params), machine(int(5)))
+        );
+        let err = parse_response(list!(int(1), int(1), stage.clone(), state)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "current_state.machines[2].params: expected a list, found 5"
+        );
 
-fn parse_machine_and_action_result(val: Value) -> Result<(Machine, Vec<ActionResult>)> {
-    match to_vec(val.clone())?.as_slice() {
-        [machine, action_result] => Ok((
-            parse_machine(machine.clone())?,
-            parse_action_result(action_result.clone())?,
-        )),
-        _ => bail!("unexpected value: {}", val.to_string()),
+        // Out of range values are errors rather than wrapped around.
+        let beam = list!(int(2), int(0), cons(int(1), int(2)), int(1_i128 << 80));
+        assert_eq!(
+            Command::from_value(&beam).unwrap_err().to_string(),
+            "power: expected isize, found 1208925819614629174706176"
+        );
+
+        let err = parse_response(list!(int(1), int(7), stage, nil())).unwrap_err();
+        assert_eq!(
+            err,
+            ProtocolError::UnknownTag {
+                path: "current_game_state".to_string(),
+                tag: 7
+            }
+        );
     }
-}
 
-fn parse_current_state(val: Value) -> Result<Option<CurrentState>> {
-    match to_vec(val.clone())?.as_slice() {
-        [turn, obstacle, machines] => Ok(Some(CurrentState {
-            turn: to_int(turn)? as usize,
-            obstacle: parse_obstacle(obstacle.clone())?,
-            machines: to_vec(machines.clone())?
-                .into_iter()
-                .map(|val| parse_machine_and_action_result(val))
-                .collect::<Result<Vec<_>>>()?,
-        })),
-        [] => Ok(None),
-        _ => bail!("unexpected value: {}", val.to_string()),
+    #[test]
+    fn test_game_client() {
+        let (transport, peer) = channel();
+        let t = std::thread::spawn(move || {
+            let mut requests = vec![];
+            peer.serve(|req| {
+                requests.push(parse_request(demodulate_from_string(req).unwrap()).unwrap());
+                RESPONSE.to_string()
+            });
+            requests
+        });
+        let mut client = GameClient::new(12345, Box::new(transport));
+        let resp = client.join().unwrap();
+        let state = resp.current_state.as_ref().unwrap();
+        let own = state
+            .machines
+            .iter()
+            .find(|(m, _)| m.role == resp.stage_data.self_role)
+            .unwrap()
+            .0
+            .machine_id;
+        let thrust = Command::Thrust(own, Point::new(1, 0));
+        client
+            .command(&mut vec![thrust, Command::Bomb(999)].into_iter())
+            .unwrap();
+        drop(client);
+
+        let requests = t.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[1],
+            Request::Command {
+                player_key: 12345,
+                commands: vec![thrust],
+            }
+        );
     }
-}
 
-fn parse_response(val: Value) -> Result<Response> {
-    match to_vec(val.clone())?.as_slice() {
-        [tag, current_game_state, stage_data, current_state] => {
-            if to_int(tag)? != 1 {
-                bail!("tag is not 1: {}", tag.to_string());
+    // Random values for the round-trip tests. The ranges are wider than
+    // anything the server sends, including negative coordinates.
+    mod gen {
+        use crate::game::*;
+        use rand::rngs::StdRng;
+        use rand::Rng;
+
+        pub fn point(rng: &mut StdRng) -> Point {
+            Point::new(rng.gen_range(-300, 300), rng.gen_range(-300, 300))
+        }
+
+        pub fn param(rng: &mut StdRng) -> Param {
+            Param {
+                energy: rng.gen_range(0, 512),
+                laser_power: rng.gen_range(0, 128),
+                cool_down_per_turn: rng.gen_range(0, 32),
+                life: rng.gen_range(0, 256),
+            }
+        }
+
+        pub fn command(rng: &mut StdRng) -> Command {
+            let ship_num = rng.gen_range(0, 64);
+            match rng.gen_range(0, 4) {
+                0 => Command::Thrust(ship_num, point(rng)),
+                1 => Command::Bomb(ship_num),
+                2 => Command::Beam(ship_num, point(rng), rng.gen_range(0, 128)),
+                _ => Command::Split(ship_num, param(rng)),
+            }
+        }
+
+        fn action_result(rng: &mut StdRng) -> ActionResult {
+            match rng.gen_range(0, 4) {
+                0 => ActionResult::Thruster { a: point(rng) },
+                1 => ActionResult::Bomb {
+                    power: rng.gen_range(0, 512),
+                    area: rng.gen_range(0, 64),
+                },
+                2 => ActionResult::Laser {
+                    opponent: point(rng),
+                    power: rng.gen_range(0, 128),
+                    intensity: rng.gen_range(0, 384),
+                    _3: rng.gen_range(-4, 4),
+                },
+                _ => ActionResult::Split { params: param(rng) },
+            }
+        }
+
+        fn machine(rng: &mut StdRng, machine_id: isize) -> Machine {
+            Machine {
+                role: if rng.gen() {
+                    Role::ATTACKER
+                } else {
+                    Role::DEFENDER
+                },
+                machine_id,
+                position: point(rng),
+                velocity: point(rng),
+                params: param(rng),
+                heat: rng.gen_range(0, 64),
+                heat_limit: 64,
+                move_limit: rng.gen_range(1, 3),
+            }
+        }
+
+        fn obstacle(rng: &mut StdRng) -> Option<Obstacle> {
+            if rng.gen() {
+                Some(Obstacle {
+                    gravity_radius: rng.gen_range(0, 64),
+                    stage_half_size: rng.gen_range(64, 256),
+                })
+            } else {
+                None
+            }
+        }
+
+        pub fn response(rng: &mut StdRng) -> Response {
+            let current_state = if rng.gen() {
+                let machines = (0..rng.gen_range(0, 8))
+                    .map(|id| {
+                        let results = (0..rng.gen_range(0, 4))
+                            .map(|_| action_result(rng))
+                            .collect();
+                        (machine(rng, id), results)
+                    })
+                    .collect();
+                Some(CurrentState {
+                    turn: rng.gen_range(0, 256),
+                    obstacle: obstacle(rng),
+                    machines,
+                })
+            } else {
+                None
+            };
+            Response {
+                current_game_state: match rng.gen_range(0, 3) {
+                    0 => CurrentGameState::START,
+                    1 => CurrentGameState::PLAYING,
+                    _ => CurrentGameState::END,
+                },
+                stage_data: StageData {
+                    total_turns: rng.gen_range(0, 384),
+                    self_role: if rng.gen() {
+                        Role::ATTACKER
+                    } else {
+                        Role::DEFENDER
+                    },
+                    initialize_param: InitializeParam {
+                        total_cost: rng.gen_range(0, 512),
+                        thrust_limit: rng.gen_range(0, 3),
+                        heat_limit: rng.gen_range(0, 128),
+                    },
+                    obstacle: obstacle(rng),
+                    defender: if rng.gen() { Some(param(rng)) } else { None },
+                },
+                current_state,
             }
-            Ok(Response {
-                current_game_state: parse_current_game_state(current_game_state),
-                stage_data: parse_stage_data(stage_data.clone())?,
-                current_state: parse_current_state(current_state.clone())?,
-            })
         }
-        [tag] if to_int(tag)? == 0 => bail!("wrong request"),
-        _ => bail!("unexpected response value: {}", val.to_string()),
     }
-}
 
-fn send_and_receive_game_state(val: &Value) -> Result<Response> {
-    eprintln!("{:?}", val);
-    println!("{}", modulate_to_string(&val));
-    io::stdout().flush();
-    let mut resp = String::new();
-    io::stdin().read_line(&mut resp)?;
-    let resp = demodulate_from_string(&resp).unwrap();
-    let resp = parse_response(resp)?;
-    eprintln!("recieve: {:#?}", resp);
-    Ok(resp)
-}
+    #[test]
+    fn test_command_round_trip() {
+        use rand::SeedableRng;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        for _ in 0..1000 {
+            let command = gen::command(&mut rng);
+            let val = demodulate_from_string(&modulate_to_string(&command.to_value())).unwrap();
+            assert_eq!(Command::from_value(&val).unwrap(), command);
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[cfg(feature = "serde")]
     #[test]
-    fn test_parse() {
-        let resp = demodulate_from_string("110110000111011000011111011110000100000000110101111011110001000000000110110000111011100100000000111101110000100001101110100000000011110111001100100110110101011011010101101100001000011110110000111110111000010000110111010000000001111111101100001110101111101100010111110101111111101100001010111101110011001001101101010110110101011011000010011010110111001000000110110000100111111011000101111011100011000001101111110110101011010110110010000000011111101011011000011111011100010111101101111111110100001010111101111000011001000011010110110001011011000010011010110111001000000110110000100110000000000").unwrap();
-        parse_response(resp).unwrap();
+    fn test_response_json_round_trip() {
+        use rand::SeedableRng;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        for _ in 0..100 {
+            let resp = gen::response(&mut rng);
+            let json = serde_json::to_string(&resp).unwrap();
+            assert_eq!(serde_json::from_str::<Response>(&json).unwrap(), resp);
+        }
+    }
+
+    #[test]
+    fn test_response_round_trip() {
+        use rand::SeedableRng;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        for _ in 0..1000 {
+            let resp = gen::response(&mut rng);
+            let val = demodulate_from_string(&modulate_to_string(&resp.to_value())).unwrap();
+            assert_eq!(Response::from_value(&val).unwrap(), resp);
+        }
     }
 }
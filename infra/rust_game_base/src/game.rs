@@ -14,6 +14,9 @@
 // limitations under the License.
 
 use self::super::value::*;
+use crate::arena::{ValueView, View};
+use crate::convert::{FromValue, ProtocolError, ToValue};
+use rust_game_base_derive::{FromValue, ToValue};
 use std::cmp;
 use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
 
@@ -23,6 +26,7 @@ pub const BEAM_COMMAND: i128 = 2;
 pub const SPLIT_COMMAND: i128 = 3;
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Default, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Point {
     pub x: isize,
     pub y: isize,
@@ -33,12 +37,6 @@ impl Point {
         Self { x, y }
     }
 
-    pub fn to_value(&self) -> Value {
-        Value::Cons(
-            Box::new(Value::Int(self.x as i128)),
-            Box::new(Value::Int(self.y as i128)),
-        )
-    }
     pub fn lmax_distance(&self) -> isize {
         std::cmp::max(self.x.abs(), self.y.abs())
     }
@@ -57,6 +55,28 @@ impl Point {
     }
 }
 
+// A pair (x . y) rather than a list.
+impl ToValue for Point {
+    fn to_value(&self) -> Value {
+        Value::Cons(
+            Box::new(Value::Int(self.x as i128)),
+            Box::new(Value::Int(self.y as i128)),
+        )
+    }
+}
+
+impl FromValue for Point {
+    fn from_view<'a, V: ValueView<'a>>(val: V) -> Result<Point, ProtocolError> {
+        match val.view() {
+            View::Cons(x, y) => Ok(Point {
+                x: isize::from_view(x).map_err(|e| e.within("x"))?,
+                y: isize::from_view(y).map_err(|e| e.within("y"))?,
+            }),
+            _ => Err(ProtocolError::shape("(x . y)", val)),
+        }
+    }
+}
+
 impl Add for Point {
     type Output = Point;
     fn add(self, p: Point) -> Point {
@@ -99,59 +119,53 @@ impl SubAssign for Point {
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+// Sent as [tag, ship_num, ...]. The variants are in the order of the
+// *_COMMAND tags.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ToValue, FromValue)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Command {
     // Thrust(ShipNum, Point{x, y})
-    Thrust(isize, Point),
+    Thrust(
+        #[value(name = "ship_num")] isize,
+        #[value(name = "delta")] Point,
+    ),
     // SelfDestruct(ShipNum)
-    Bomb(isize),
+    Bomb(#[value(name = "ship_num")] isize),
     // Beam(ShipNum, X, Y, Power)
-    Beam(isize, Point, isize),
+    Beam(
+        #[value(name = "ship_num")] isize,
+        #[value(name = "target")] Point,
+        #[value(name = "power")] isize,
+    ),
     // Split(ShipNum, Param)
-    Split(isize, Param),
+    Split(
+        #[value(name = "ship_num")] isize,
+        #[value(name = "params")] Param,
+    ),
 }
 
 impl Command {
-    pub fn to_value(&self) -> Value {
-        use crate::dsl::*;
-
-        match self {
-            //   send: [0, SHIP_NUM, (X . Y)]
-            &Command::Thrust(ship_num, ref pos) => {
-                list!(int(THRUST_COMMAND), int(ship_num), pos.to_value())
-            }
-            // send [1, SHIP_NUM]
-            &Command::Bomb(ship_num) => list!(int(SELF_DESTRUCT_COMMAND), int(ship_num)),
-            // send: [2, SHIP_NUM, ( X . Y ), POWER] =>
-            &Command::Beam(ship_num, point, power) => list!(
-                int(BEAM_COMMAND),
-                int(ship_num),
-                point.to_value(),
-                int(power)
-            ),
-            &Command::Split(ship_num, ref param) => list!(
-                int(SPLIT_COMMAND),
-                int(ship_num),
-                list!(
-                    int(param.energy),
-                    int(param.laser_power),
-                    int(param.cool_down_per_turn),
-                    int(param.life)
-                )
-            ),
+    pub fn ship_num(&self) -> isize {
+        match *self {
+            Command::Thrust(ship_num, _)
+            | Command::Bomb(ship_num)
+            | Command::Beam(ship_num, _, _)
+            | Command::Split(ship_num, _) => ship_num,
         }
     }
 }
 
 // 0/START, 1/PLAYING, 2/END (cf: 公式)
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ToValue, FromValue)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CurrentGameState {
     START,
     PLAYING,
     END,
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ToValue, FromValue)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Role {
     ATTACKER,
     DEFENDER,
@@ -172,7 +186,8 @@ impl Role {
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ToValue, FromValue)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Obstacle {
     // 重力源の半径 (|x| と |y| がともにこれ以下になると死. 移動中にかすめてもセーフ),
     pub gravity_radius: usize,
@@ -180,7 +195,8 @@ pub struct Obstacle {
     pub stage_half_size: usize,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, ToValue, FromValue)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StageData {
     pub total_turns: usize,
     pub self_role: Role, // whether you're an attacker or a defender.
@@ -189,22 +205,48 @@ pub struct StageData {
     pub defender: Option<Param>, // Attacker can receive this.
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, ToValue, FromValue)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InitializeParam {
     pub total_cost: usize,
     pub thrust_limit: usize,
     pub heat_limit: usize,
 }
 
-// deserialized response.
+// deserialized request. Only a game server needs to parse these.
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Request {
+    // tag = 1
+    Create,
+    // 2
+    Join {
+        player_key: i128,
+    },
+    // 3, params is None in the tutorial mode.
+    Start {
+        player_key: i128,
+        params: Option<Param>,
+    },
+    // 4
+    Command {
+        player_key: i128,
+        commands: Vec<Command>,
+    },
+}
+
+// deserialized response.
+#[derive(Clone, Debug, Eq, PartialEq, ToValue, FromValue)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[value(tag = 1)]
 pub struct Response {
     pub current_game_state: CurrentGameState,
     pub stage_data: StageData,
     pub current_state: Option<CurrentState>,
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Default)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Default, ToValue, FromValue)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Param {
     // コレがなくなると、 Thruster が吹けない
     pub energy: usize,
@@ -216,7 +258,8 @@ pub struct Param {
     pub life: usize,
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Default)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Default, ToValue, FromValue)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Machine {
     pub role: Role,
     // 機体 ID. 多分自陣営/敵陣営通して unique.
@@ -230,7 +273,8 @@ pub struct Machine {
     pub move_limit: usize,
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ToValue, FromValue)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ActionResult {
     // tag = 0
     Thruster {
@@ -255,13 +299,46 @@ pub enum ActionResult {
     },
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Default)]
+#[derive(Clone, Debug, Eq, PartialEq, Default, ToValue, FromValue)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CurrentState {
     pub turn: usize, // 現在のターン数
     pub obstacle: Option<Obstacle>,
+    #[value(with = "machines")]
     pub machines: Vec<(Machine, Vec<ActionResult>)>,
 }
 
+// The machine fields and "results" share one path segment, "machines[i]", so
+// errors read like current_state.machines[2].params.
+mod machines {
+    use super::*;
+    use crate::convert::list_items;
+
+    pub fn to_value(machines: &[(Machine, Vec<ActionResult>)]) -> Value {
+        machines.to_value()
+    }
+
+    pub fn from_view<'a, V: ValueView<'a>>(
+        val: V,
+    ) -> Result<Vec<(Machine, Vec<ActionResult>)>, ProtocolError> {
+        let machine = |val: V| -> Result<_, ProtocolError> {
+            let items = list_items(val)
+                .filter(|items| items.len() == 2)
+                .ok_or_else(|| ProtocolError::shape("[machine, results]", val))?;
+            Ok((
+                Machine::from_view(items[0])?,
+                Vec::from_view(items[1]).map_err(|e| e.within("results"))?,
+            ))
+        };
+        list_items(val)
+            .ok_or_else(|| ProtocolError::shape("a list", val))?
+            .into_iter()
+            .enumerate()
+            .map(|(i, m)| machine(m).map_err(|e| e.within(&format!("[{}]", i))))
+            .collect()
+    }
+}
+
 // Utilities.
 
 // Returns machine ids of the given role.
@@ -295,7 +372,8 @@ pub fn get_results_by_id(state: &CurrentState, machine_id: isize) -> Option<&Vec
         .map(|(m, r)| r)
 }
 
-// Returns the intensity of the laser.
+// Returns the intensity of the laser at `d` away from the shooter.
+// The simulator uses this as well. Observed intensities go in testdata/laser.txt.
 pub fn get_intensity(d: &Point, power: usize) -> usize {
     let dx = d.x.abs() as usize;
     let dy = d.y.abs() as usize;
@@ -18,11 +18,24 @@
 #[macro_use]
 pub mod dsl;
 pub mod actions;
+pub mod arena;
+pub mod conformance;
+pub mod convert;
 pub mod framework;
 pub mod game;
+pub mod pretty;
+pub mod replay;
+pub mod rules;
 pub mod simulator;
+pub mod transport;
 pub mod value;
 
+pub use self::convert::{FromValue, ProtocolError, ToValue};
 pub use self::framework::*;
 pub use self::game::*;
+pub use self::rules::*;
 pub use self::simulator::*;
+pub use rust_game_base_derive::{FromValue, ToValue};
+
+// Lets the derive macros name this crate as ::rust_game_base from inside it.
+extern crate self as rust_game_base;
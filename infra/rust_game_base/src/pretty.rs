@@ -0,0 +1,376 @@
+// Copyright 2020 Google LLC
+// Copyright 2020 Team Spacecat
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Multi-line printing and comparison of values for logs. Lists that do not fit
+// in the width get one element per line:
+//
+//   (1
+//    1
+//    (256 0 (512 1 64) nil nil)
+//    ...)
+//
+// With Labels, the elements of known shapes are named, e.g. RESPONSE prints
+// "stage_data: (256 ...)" and diff paths like "current_state.turn".
+
+use crate::value::*;
+use std::fmt::{self, Write};
+
+// Names for the elements of a value, following the lists of the protocol.
+pub enum Labels {
+    None,
+    // The elements of a list, in order.
+    Fields(&'static [(&'static str, Labels)]),
+    // Every element of a list has these labels.
+    Each(&'static Labels),
+}
+
+const PARAM: Labels = Labels::Fields(&[
+    ("energy", Labels::None),
+    ("laser_power", Labels::None),
+    ("cool_down_per_turn", Labels::None),
+    ("life", Labels::None),
+]);
+
+const MACHINE: Labels = Labels::Fields(&[
+    ("role", Labels::None),
+    ("machine_id", Labels::None),
+    ("position", Labels::None),
+    ("velocity", Labels::None),
+    ("params", PARAM),
+    ("heat", Labels::None),
+    ("heat_limit", Labels::None),
+    ("move_limit", Labels::None),
+]);
+
+// Responses to JOIN, START and COMMAND.
+pub const RESPONSE: Labels = Labels::Fields(&[
+    ("tag", Labels::None),
+    ("current_game_state", Labels::None),
+    (
+        "stage_data",
+        Labels::Fields(&[
+            ("total_turns", Labels::None),
+            ("self_role", Labels::None),
+            ("initialize_param", Labels::None),
+            ("obstacle", Labels::None),
+            ("defender", PARAM),
+        ]),
+    ),
+    (
+        "current_state",
+        Labels::Fields(&[
+            ("turn", Labels::None),
+            ("obstacle", Labels::None),
+            (
+                "machines",
+                Labels::Each(&Labels::Fields(&[
+                    ("machine", MACHINE),
+                    ("results", Labels::None),
+                ])),
+            ),
+        ]),
+    ),
+]);
+
+impl Labels {
+    fn element(&self, i: usize) -> (Option<&'static str>, &Labels) {
+        match self {
+            Labels::Fields(fields) if i < fields.len() => (Some(fields[i].0), &fields[i].1),
+            Labels::Each(labels) => (None, labels),
+            _ => (None, &Labels::None),
+        }
+    }
+
+    fn is_none(&self) -> bool {
+        matches!(self, Labels::None)
+    }
+}
+
+// Fails once more than `limit` bytes are written.
+struct Limited {
+    len: usize,
+    limit: usize,
+}
+
+impl Write for Limited {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.len += s.len();
+        if self.len > self.limit {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn fits(val: &Value, limit: usize) -> bool {
+    write!(Limited { len: 0, limit }, "{}", val).is_ok()
+}
+
+// The car of every cell of a list, and what ends it.
+fn elements(val: &Value) -> (Vec<&Value>, &Value) {
+    let mut items = vec![];
+    let mut rest = val;
+    while let Value::Cons(car, cdr) = rest {
+        items.push(&**car);
+        rest = cdr;
+    }
+    (items, rest)
+}
+
+enum Job<'a> {
+    Val(&'a Value, &'a Labels),
+    Str(&'static str),
+    Label(&'static str),
+    Newline(usize),
+}
+
+pub fn pretty(val: &Value, width: usize) -> String {
+    pretty_labeled(val, width, &Labels::None)
+}
+
+// Labeled lists are always one element per line so that every label shows.
+pub fn pretty_labeled(val: &Value, width: usize, labels: &Labels) -> String {
+    let mut out = String::new();
+    let mut line_start = 0;
+    let mut jobs = vec![Job::Val(val, labels)];
+    while let Some(job) = jobs.pop() {
+        let (val, labels) = match job {
+            Job::Val(val, labels) => (val, labels),
+            Job::Str(s) => {
+                out.push_str(s);
+                continue;
+            }
+            Job::Label(name) => {
+                out.push_str(name);
+                out.push_str(": ");
+                continue;
+            }
+            Job::Newline(indent) => {
+                out.push('\n');
+                line_start = out.len();
+                out.push_str(&" ".repeat(indent));
+                continue;
+            }
+        };
+        let col = out.len() - line_start;
+        let labeled = !labels.is_none() && is_list(val);
+        // Past the width, deeper levels stay on one line instead of indenting
+        // further.
+        if col >= width || !labeled && fits(val, width - col) {
+            write!(out, "{}", val).unwrap();
+            continue;
+        }
+        match val {
+            Value::Cons(..) => {
+                let (items, rest) = elements(val);
+                jobs.push(Job::Str(")"));
+                if let Value::Nil = rest {
+                } else {
+                    jobs.push(Job::Val(rest, &Labels::None));
+                    jobs.push(Job::Str(". "));
+                    jobs.push(Job::Newline(col + 1));
+                }
+                for (i, item) in items.into_iter().enumerate().rev() {
+                    let (name, labels) = labels.element(i);
+                    jobs.push(Job::Val(item, labels));
+                    if let Some(name) = name {
+                        jobs.push(Job::Label(name));
+                    }
+                    if i > 0 {
+                        jobs.push(Job::Newline(col + 1));
+                    }
+                }
+                jobs.push(Job::Str("("));
+            }
+            _ => write!(out, "{}", val).unwrap(),
+        }
+    }
+    out
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Difference {
+    // e.g. "[3][0]", or "current_state.turn" with labels. A path ending in
+    // "[i..]" is the rest of a list from the i-th element.
+    pub path: String,
+    pub left: String,
+    pub right: String,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "value"
+        } else {
+            &self.path
+        };
+        write!(f, "{}: {} != {}", path, self.left, self.right)
+    }
+}
+
+fn same_leaf(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => a == b,
+        (Value::BigInt(a), Value::BigInt(b)) => a == b,
+        (Value::Nil, Value::Nil) => true,
+        _ => false,
+    }
+}
+
+fn join(path: &str, name: Option<&str>, i: usize) -> String {
+    match name {
+        Some(name) if path.is_empty() => name.to_string(),
+        Some(name) => format!("{}.{}", path, name),
+        None => format!("{}[{}]", path, i),
+    }
+}
+
+// Every difference between `a` and `b`, in order.
+pub fn diff(a: &Value, b: &Value) -> Vec<Difference> {
+    diff_labeled(a, b, &Labels::None)
+}
+
+pub fn first_diff(a: &Value, b: &Value) -> Option<Difference> {
+    diff_labeled(a, b, &Labels::None).into_iter().next()
+}
+
+pub fn diff_labeled(a: &Value, b: &Value, labels: &Labels) -> Vec<Difference> {
+    let mut diffs = vec![];
+    let mut stack = vec![(a, b, String::new(), labels)];
+    while let Some((a, b, path, labels)) = stack.pop() {
+        if let (Value::Cons(..), Value::Cons(..)) = (a, b) {
+            let (a_items, a_rest) = elements(a);
+            let (b_items, b_rest) = elements(b);
+            let n = a_items.len().min(b_items.len());
+            // The rest after the common elements, when the lists differ there.
+            let (a_rest, b_rest) = if a_items.len() == b_items.len() {
+                (a_rest, b_rest)
+            } else {
+                (nth_rest(a, n), nth_rest(b, n))
+            };
+            let mut children = vec![];
+            for (i, (a, b)) in a_items.into_iter().zip(b_items).enumerate() {
+                let (name, labels) = labels.element(i);
+                children.push((a, b, join(&path, name, i), labels));
+            }
+            if !same_leaf(a_rest, b_rest) {
+                let path = format!("{}[{}..]", path, n);
+                children.push((a_rest, b_rest, path, &Labels::None));
+            }
+            stack.extend(children.into_iter().rev());
+        } else if !same_leaf(a, b) {
+            diffs.push(Difference {
+                path,
+                left: a.to_string(),
+                right: b.to_string(),
+            });
+        }
+    }
+    diffs
+}
+
+fn nth_rest(val: &Value, n: usize) -> &Value {
+    let mut rest = val;
+    for _ in 0..n {
+        if let Value::Cons(_, cdr) = rest {
+            rest = cdr;
+        }
+    }
+    rest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Value {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_pretty() {
+        let val = parse("(1 (2 3) (4 . 5) nil)");
+        assert_eq!(pretty(&val, 80), val.to_string());
+        assert_eq!(pretty(&val, 12), "(1\n (2 3)\n (4 . 5)\n nil)");
+        assert_eq!(pretty(&parse("((1 2 3) . 4)"), 7), "((1\n  2\n  3)\n . 4)");
+
+        let resp = parse(
+            "(1 1 (256 0 (512 1 64) nil nil) \
+             (3 nil (((0 0 (1 . 2) (0 . 0) (1 2 3 4) 0 64 1) nil))))",
+        );
+        assert_eq!(
+            pretty_labeled(&resp, 60, &RESPONSE),
+            "\
+(tag: 1
+ current_game_state: 1
+ stage_data: (total_turns: 256
+              self_role: 0
+              initialize_param: (512 1 64)
+              obstacle: nil
+              defender: nil)
+ current_state: (turn: 3
+                 obstacle: nil
+                 machines: ((machine: (role: 0
+                                       machine_id: 0
+                                       position: (1 . 2)
+                                       velocity: (0 . 0)
+                                       params: (energy: 1
+                                                laser_power: 2
+                                                cool_down_per_turn: 3
+                                                life: 4)
+                                       heat: 0
+                                       heat_limit: 64
+                                       move_limit: 1)
+                             results: nil))))"
+        );
+    }
+
+    #[test]
+    fn test_pretty_deep() {
+        let deep = (0..100_000).fold(Value::Nil, |acc, i| {
+            Value::Cons(Box::new(acc), Box::new(Value::Int(i)))
+        });
+        let out = pretty(&deep, 80);
+        // The levels past column 80 are on one long line.
+        assert_eq!(out.lines().count(), 81);
+        assert_eq!(out.lines().filter(|line| line.len() > 1000).count(), 1);
+        assert!(out.ends_with(" . 99999)"));
+    }
+
+    #[test]
+    fn test_diff() {
+        let a = parse("(1 (2 3) 4 5)");
+        assert_eq!(diff(&a, &a), vec![]);
+        let diffs = diff(&a, &parse("(1 (2 7) 4)"))
+            .iter()
+            .map(|d| d.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(diffs, vec!["[1][1]: 3 != 7", "[3..]: (5) != nil"]);
+        assert_eq!(
+            first_diff(&parse("(1 . 2)"), &parse("(1 . 3)"))
+                .unwrap()
+                .to_string(),
+            "[1..]: 2 != 3"
+        );
+        assert_eq!(first_diff(&a, &parse("5")).unwrap().path, "");
+
+        let resp = |turn| parse(&format!("(1 1 (256 0 nil nil nil) ({} nil nil))", turn));
+        assert_eq!(
+            diff_labeled(&resp(3), &resp(4), &RESPONSE)[0].to_string(),
+            "current_state.turn: 3 != 4"
+        );
+    }
+}
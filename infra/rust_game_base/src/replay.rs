@@ -0,0 +1,284 @@
+// Copyright 2020 Google LLC
+// Copyright 2020 Team Spacecat
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// A replay file is what a player saw of a match, one record per line, with
+// the values in the list notation of Value:
+//
+//   stage <StageData>
+//   game <CurrentGameState>
+//   state <CurrentState>
+//   commands <Vec<Command>>
+//   state <CurrentState>
+//   ...
+//
+// `stage` and `game` are written when they change, and `commands` are the ones
+// sent in the state above them. Exchanges that don't decode, like the create
+// request or a rejected one, are kept modulated in comments. Empty lines and
+// lines starting with '#' are ignored.
+
+use crate::convert::*;
+use crate::framework::*;
+use crate::game::*;
+use crate::transport::Transport;
+use crate::value::*;
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+// Wraps a transport and appends every exchange to `out`.
+pub struct Recorder<W: Write> {
+    inner: Box<dyn Transport>,
+    out: W,
+    // What was written last, to write only changes.
+    stage_data: Option<StageData>,
+    current_game_state: Option<CurrentGameState>,
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(inner: Box<dyn Transport>, out: W) -> Recorder<W> {
+        Recorder {
+            inner,
+            out,
+            stage_data: None,
+            current_game_state: None,
+        }
+    }
+
+    fn record(&mut self, request: &str, response: &str) -> Result<()> {
+        let parsed = (
+            demodulate_from_string(request).map(parse_request),
+            demodulate_from_string(response).map(parse_response),
+        );
+        let (request, resp) = match parsed {
+            (Some(Ok(req)), Some(Ok(resp))) if req != Request::Create => (req, resp),
+            _ => {
+                writeln!(self.out, "# request {}", request)?;
+                writeln!(self.out, "# response {}", response)?;
+                return Ok(());
+            }
+        };
+        if let Request::Command { commands, .. } = request {
+            writeln!(self.out, "commands {}", commands.to_value())?;
+        }
+        if self.stage_data.as_ref() != Some(&resp.stage_data) {
+            writeln!(self.out, "stage {}", resp.stage_data.to_value())?;
+            self.stage_data = Some(resp.stage_data);
+        }
+        if self.current_game_state != Some(resp.current_game_state) {
+            writeln!(self.out, "game {}", resp.current_game_state.to_value())?;
+            self.current_game_state = Some(resp.current_game_state);
+        }
+        if let Some(state) = &resp.current_state {
+            writeln!(self.out, "state {}", state.to_value())?;
+        }
+        Ok(())
+    }
+}
+
+impl Recorder<File> {
+    pub fn create(inner: Box<dyn Transport>, path: impl AsRef<Path>) -> Result<Recorder<File>> {
+        let path = path.as_ref();
+        let out =
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+        Ok(Recorder::new(inner, out))
+    }
+}
+
+impl<W: Write> Transport for Recorder<W> {
+    fn send(&mut self, request: &str) -> Result<String> {
+        let resp = self.inner.send(request)?;
+        self.record(request, &resp)?;
+        // Flush per exchange so that a crashed bot still leaves a usable replay.
+        self.out.flush()?;
+        Ok(resp)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Replay {
+    // From the latest response.
+    pub stage_data: StageData,
+    pub current_game_state: CurrentGameState,
+    // Each observed state with the commands sent in it. The commands are only
+    // the recording player's, and the final state has none.
+    pub turns: Vec<(CurrentState, Vec<Command>)>,
+}
+
+impl Replay {
+    pub fn load(path: impl AsRef<Path>) -> Result<Replay> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        Replay::from_reader(BufReader::new(file))
+            .with_context(|| format!("failed to load {}", path.display()))
+    }
+
+    pub fn from_reader(reader: impl BufRead) -> Result<Replay> {
+        let mut stage_data = None;
+        let mut current_game_state = None;
+        let mut turns: Vec<(CurrentState, Vec<Command>)> = vec![];
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let at = || format!("line {}", i + 1);
+            let (kind, rest) = match line.find(char::is_whitespace) {
+                Some(n) => (&line[..n], line[n..].trim()),
+                None => bail!("line {}: malformed line", i + 1),
+            };
+            match kind {
+                "stage" => stage_data = Some(parse_record(rest).with_context(at)?),
+                "game" => current_game_state = Some(parse_record(rest).with_context(at)?),
+                "state" => turns.push((parse_record(rest).with_context(at)?, vec![])),
+                "commands" => match turns.last_mut() {
+                    Some(turn) => turn.1 = parse_record(rest).with_context(at)?,
+                    None => bail!("line {}: commands before any state", i + 1),
+                },
+                _ => bail!("line {}: unknown record kind: {}", i + 1, kind),
+            }
+        }
+        match (stage_data, current_game_state) {
+            (Some(stage_data), Some(current_game_state)) => Ok(Replay {
+                stage_data,
+                current_game_state,
+                turns,
+            }),
+            _ => bail!("no stage data or game state in the replay"),
+        }
+    }
+}
+
+fn parse_record<T: FromValue>(s: &str) -> Result<T> {
+    let val: Value = s.parse()?;
+    Ok(T::from_value(&val)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl::*;
+    use crate::transport::channel;
+
+    fn response(turn: usize, game_state: CurrentGameState) -> Response {
+        let machine = Machine {
+            role: Role::ATTACKER,
+            machine_id: 0,
+            position: Point::new(48, turn as isize),
+            params: Param {
+                energy: 100,
+                laser_power: 0,
+                cool_down_per_turn: 8,
+                life: 1,
+            },
+            heat_limit: 64,
+            move_limit: 1,
+            ..Machine::default()
+        };
+        Response {
+            current_game_state: game_state,
+            stage_data: StageData {
+                total_turns: 256,
+                self_role: Role::ATTACKER,
+                initialize_param: InitializeParam {
+                    total_cost: 512,
+                    thrust_limit: 1,
+                    heat_limit: 64,
+                },
+                obstacle: None,
+                defender: None,
+            },
+            current_state: Some(CurrentState {
+                turn,
+                obstacle: None,
+                machines: vec![(machine, vec![])],
+            }),
+        }
+    }
+
+    #[test]
+    fn test_record_and_load() {
+        let path = std::env::temp_dir().join(format!("replay-{}.txt", std::process::id()));
+        let (transport, peer) = channel();
+        let t = std::thread::spawn(move || {
+            let mut turn = 0;
+            peer.serve(|req| {
+                let resp = match parse_request(demodulate_from_string(req).unwrap()).unwrap() {
+                    Request::Join { .. } => {
+                        let mut resp = response(0, CurrentGameState::START);
+                        resp.current_state = None;
+                        resp.to_value()
+                    }
+                    Request::Start { .. } => response(0, CurrentGameState::PLAYING).to_value(),
+                    Request::Command { .. } => {
+                        turn += 1;
+                        response(turn, CurrentGameState::PLAYING).to_value()
+                    }
+                    Request::Create => list!(int(0)),
+                };
+                modulate_to_string(&resp)
+            });
+        });
+
+        let recorder = Recorder::create(Box::new(transport), &path).unwrap();
+        let mut client = GameClient::new(1, Box::new(recorder));
+        client.join().unwrap();
+        client.start(&Param::default()).unwrap();
+        let thrust = Command::Thrust(0, Point::new(1, 0));
+        client.command(&mut vec![thrust].into_iter()).unwrap();
+        client.command(&mut vec![].into_iter()).unwrap();
+        drop(client);
+        t.join().unwrap();
+
+        let replay = Replay::load(&path).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let kinds = text
+            .lines()
+            .map(|line| line.split(' ').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            ["stage", "game", "game", "state", "commands", "state", "commands", "state"]
+        );
+        assert!(text.contains(&format!("commands {}", vec![thrust].to_value())));
+        assert_eq!(
+            replay.stage_data,
+            response(0, CurrentGameState::PLAYING).stage_data
+        );
+        assert_eq!(replay.current_game_state, CurrentGameState::PLAYING);
+        let turns = replay
+            .turns
+            .iter()
+            .map(|(state, commands)| (state.turn, commands.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(turns, vec![(0, vec![thrust]), (1, vec![]), (2, vec![])]);
+    }
+
+    #[test]
+    fn test_load_errors() {
+        // Only a JOIN rejected by the server, kept as comments.
+        let join = modulate_to_string(&list!(int(2), int(1), nil()));
+        let replay = format!("# request {}\n# response 1101000\n", join);
+        assert!(Replay::from_reader(replay.as_bytes()).is_err());
+        assert!(Replay::from_reader("response 1101000\n".as_bytes()).is_err());
+        assert!(Replay::from_reader("commands nil\n".as_bytes()).is_err());
+        assert!(Replay::from_reader("state (1 2)\n".as_bytes()).is_err());
+        assert!(Replay::from_reader("game 1\nstate (0 nil nil)\n".as_bytes()).is_err());
+        assert!(Replay::from_reader("stage\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+// Copyright 2020 Google LLC
+// Copyright 2020 Team Spacecat
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::game::*;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CommandErrorKind {
+    // The game has already reached StageData::total_turns.
+    GameOver,
+    // No machine has the given ship number.
+    UnknownMachine,
+    // The machine belongs to the opponent.
+    NotOwnMachine,
+    // The machine has no params left.
+    DeadMachine,
+    // Thrust(0, 0) cannot be chosen in alien GUI.
+    ZeroThrust,
+    // More than one thrust for the same machine.
+    DuplicateThrust,
+    // |x| or |y| exceeds Machine::move_limit.
+    ThrustTooLarge,
+    // Negative, or more than Param::laser_power.
+    InvalidBeamPower,
+    // The child params are not covered by the parent, or nobody keeps a life.
+    InvalidSplit,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CommandError {
+    // Index of the offending command in the given slice.
+    pub index: usize,
+    pub command: Command,
+    pub kind: CommandErrorKind,
+}
+
+// Returns true if `parent` can give `child` away and still be alive.
+pub fn can_split(parent: &Param, child: &Param) -> bool {
+    child.life >= 1
+        && child.energy <= parent.energy
+        && child.laser_power <= parent.laser_power
+        && child.cool_down_per_turn <= parent.cool_down_per_turn
+        && child.life < parent.life
+}
+
+fn check_command(
+    state: &CurrentState,
+    role: Role,
+    command: &Command,
+    thrust_ids: &mut Vec<isize>,
+) -> Option<CommandErrorKind> {
+    let machine = match get_machine_by_id(state, command.ship_num()) {
+        Some(m) => m,
+        None => return Some(CommandErrorKind::UnknownMachine),
+    };
+    if machine.role != role {
+        return Some(CommandErrorKind::NotOwnMachine);
+    }
+    if machine.params == Param::default() {
+        return Some(CommandErrorKind::DeadMachine);
+    }
+    match *command {
+        Command::Thrust(ship_num, delta) => {
+            if delta.lmax_distance() == 0 {
+                return Some(CommandErrorKind::ZeroThrust);
+            }
+            if thrust_ids.contains(&ship_num) {
+                return Some(CommandErrorKind::DuplicateThrust);
+            }
+            thrust_ids.push(ship_num);
+            if delta.lmax_distance() as usize > machine.move_limit {
+                return Some(CommandErrorKind::ThrustTooLarge);
+            }
+        }
+        Command::Bomb(_) => (),
+        Command::Beam(_, _, power) => {
+            if power < 0 || power as usize > machine.params.laser_power {
+                return Some(CommandErrorKind::InvalidBeamPower);
+            }
+        }
+        Command::Split(_, ref params) => {
+            if !can_split(&machine.params, params) {
+                return Some(CommandErrorKind::InvalidSplit);
+            }
+        }
+    }
+    None
+}
+
+// Checks that `commands` are legal for `role` in the current state.
+// Returns every violation found, not only the first one.
+pub fn validate_commands(
+    stage: &StageData,
+    state: &CurrentState,
+    role: Role,
+    commands: &[Command],
+) -> Result<(), Vec<CommandError>> {
+    let game_over = state.turn >= stage.total_turns;
+    let mut thrust_ids = Vec::new();
+    let errors = commands
+        .iter()
+        .enumerate()
+        .filter_map(|(index, command)| {
+            let kind = if game_over {
+                Some(CommandErrorKind::GameOver)
+            } else {
+                check_command(state, role, command, &mut thrust_ids)
+            };
+            kind.map(|kind| CommandError {
+                index,
+                command: *command,
+                kind,
+            })
+        })
+        .collect::<Vec<_>>();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_state() -> (StageData, CurrentState) {
+        let attacker = Machine {
+            role: Role::ATTACKER,
+            machine_id: 0,
+            position: Point { x: 48, y: 0 },
+            velocity: Point { x: 0, y: 0 },
+            params: Param {
+                energy: 10,
+                laser_power: 16,
+                cool_down_per_turn: 8,
+                life: 2,
+            },
+            heat: 0,
+            heat_limit: 64,
+            move_limit: 1,
+        };
+        let defender = Machine {
+            role: Role::DEFENDER,
+            machine_id: 1,
+            position: Point { x: -48, y: 0 },
+            ..attacker
+        };
+        let stage = StageData {
+            total_turns: 256,
+            self_role: Role::ATTACKER,
+            initialize_param: InitializeParam {
+                total_cost: 512,
+                thrust_limit: 1,
+                heat_limit: 64,
+            },
+            obstacle: None,
+            defender: None,
+        };
+        let state = CurrentState {
+            turn: 3,
+            obstacle: None,
+            machines: vec![(attacker, vec![]), (defender, vec![])],
+        };
+        (stage, state)
+    }
+
+    fn kinds(result: Result<(), Vec<CommandError>>) -> Vec<(usize, CommandErrorKind)> {
+        result
+            .unwrap_err()
+            .into_iter()
+            .map(|e| (e.index, e.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_commands() {
+        let (stage, state) = test_state();
+        let commands = [
+            Command::Thrust(0, Point { x: 1, y: -1 }),
+            Command::Beam(0, Point { x: -48, y: 0 }, 16),
+            Command::Split(
+                0,
+                Param {
+                    energy: 5,
+                    laser_power: 0,
+                    cool_down_per_turn: 0,
+                    life: 1,
+                },
+            ),
+        ];
+        assert_eq!(
+            validate_commands(&stage, &state, Role::ATTACKER, &commands),
+            Ok(())
+        );
+        assert_eq!(
+            validate_commands(&stage, &state, Role::DEFENDER, &[]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_invalid_commands() {
+        let (stage, state) = test_state();
+        let commands = [
+            Command::Thrust(0, Point { x: 0, y: 0 }),
+            Command::Thrust(0, Point { x: 1, y: 0 }),
+            Command::Thrust(0, Point { x: -1, y: 0 }),
+            Command::Thrust(1, Point { x: 1, y: 0 }),
+            Command::Bomb(7),
+            Command::Beam(0, Point { x: -48, y: 0 }, 17),
+            Command::Split(
+                0,
+                Param {
+                    energy: 5,
+                    laser_power: 0,
+                    cool_down_per_turn: 0,
+                    life: 2,
+                },
+            ),
+        ];
+        assert_eq!(
+            kinds(validate_commands(&stage, &state, Role::ATTACKER, &commands)),
+            vec![
+                (0, CommandErrorKind::ZeroThrust),
+                (2, CommandErrorKind::DuplicateThrust),
+                (3, CommandErrorKind::NotOwnMachine),
+                (4, CommandErrorKind::UnknownMachine),
+                (5, CommandErrorKind::InvalidBeamPower),
+                (6, CommandErrorKind::InvalidSplit),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_thrust_limit_and_game_over() {
+        let (stage, mut state) = test_state();
+        let commands = [Command::Thrust(0, Point { x: 2, y: 0 })];
+        assert_eq!(
+            kinds(validate_commands(&stage, &state, Role::ATTACKER, &commands)),
+            vec![(0, CommandErrorKind::ThrustTooLarge)]
+        );
+
+        state.turn = stage.total_turns;
+        let commands = [Command::Bomb(0)];
+        assert_eq!(
+            kinds(validate_commands(&stage, &state, Role::ATTACKER, &commands)),
+            vec![(0, CommandErrorKind::GameOver)]
+        );
+    }
+}
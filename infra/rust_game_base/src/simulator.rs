@@ -14,6 +14,7 @@
 // limitations under the License.
 
 use self::super::game::*;
+use self::super::rules::*;
 use std::cmp::{max, min};
 
 const THRUST_HEAT: usize = 8;
@@ -79,61 +80,27 @@ fn lookup_machine(s: &CurrentState, id: isize) -> Option<Machine> {
     return None;
 }
 
-fn laser_damage_base(delta: &Point) -> isize {
-    // FIXME need experiments
-    if (delta.x == 0 || delta.y == 0) {
-        3
-    } else if (delta.x == delta.y || delta.x == -delta.y) {
-        3
-    } else if (delta.x == delta.y * 2
-        || delta.x == -delta.y * 2
-        || delta.x * 2 == delta.y
-        || -delta.x * 2 == delta.y)
-    {
-        0
-    } else if (delta.x == delta.y * 3
-        || delta.x == -delta.y * 3
-        || delta.x * 3 == delta.y
-        || -delta.x * 3 == delta.y)
-    {
-        2
-    } else if (delta.x * 4 == delta.y * 3
-        || delta.x * 4 == -delta.y * 3
-        || delta.x * 3 == delta.y * 4
-        || -delta.x * 3 == delta.y * 4)
-    {
-        2
-    } else {
-        1
-    }
-}
-
 fn do_laser_helper(s: &mut CurrentState, shipnum: isize, target: &Point, power: isize) {
     let origin = lookup_machine(s, shipnum).unwrap();
-    let dx = *target - origin.position;
-    let damage_base = laser_damage_base(&dx);
-    let diminish = dx.lmax_distance() - 1;
-    let damage = max(damage_base * power - diminish, 0); // should be OK because it's isize
+    let intensity = get_intensity(&(*target - origin.position), power as usize);
     for mpair in &mut s.machines {
         // check position
-        let m = &mut mpair.0;
-        let dpos = m.position - *target;
-        let dist = dpos.lmax_distance();
+        let dist = (mpair.0.position - *target).lmax_distance();
         let finaldamage = if dist > 15 {
             0
         } else {
-            damage as usize >> (2 * dist as usize)
+            intensity >> (2 * dist as usize)
         };
-        mpair.0.heat += finaldamage as usize;
+        mpair.0.heat += finaldamage;
 
-        if (mpair.0.machine_id == shipnum) {
+        if mpair.0.machine_id == shipnum {
             mpair.0.heat += power as usize; // self heat dmg
             mpair.1.push(ActionResult::Laser {
                 opponent: *target,
                 power: power as usize,
-                intensity: 0, // FIXME TODO.
-                _3: 0,        // FIXME TODO.
-            }) // FIXME TODO: damage
+                intensity,
+                _3: 4, // As in every response in testdata/laser.txt.
+            })
         }
     }
 }
@@ -267,24 +234,53 @@ fn do_self_destruct(s: &mut CurrentState, all_actions: &Vec<Command>) {
     }
 }
 
+fn do_split_helper(s: &mut CurrentState, shipnum: isize, params: &Param) {
+    // Machine ids are allocated sequentially across both roles.
+    let new_id = s
+        .machines
+        .iter()
+        .map(|(m, _)| m.machine_id)
+        .max()
+        .map_or(0, |id| id + 1);
+    let (parent, results) = match s.machines.iter_mut().find(|(m, _)| m.machine_id == shipnum) {
+        Some((m, r)) => (m, r),
+        None => return,
+    };
+    if !can_split(&parent.params, params) {
+        return;
+    }
+    parent.params.energy -= params.energy;
+    parent.params.laser_power -= params.laser_power;
+    parent.params.cool_down_per_turn -= params.cool_down_per_turn;
+    parent.params.life -= params.life;
+    results.push(ActionResult::Split { params: *params });
+
+    let child = Machine {
+        machine_id: new_id,
+        params: *params,
+        heat: 0,
+        ..*parent
+    };
+    s.machines.push((child, vec![]));
+}
+
+fn state_update_splits(cstate: &mut CurrentState, commands: &[Command]) {
+    for c in commands {
+        if let Command::Split(shipnum, params) = c {
+            do_split_helper(cstate, *shipnum, params);
+        }
+    }
+}
+
 fn state_update_damages(cstate: &mut CurrentState, commands: &Vec<Command>) {
     do_laser(cstate, commands);
     do_self_destruct(cstate, commands);
 }
 
 fn state_update_velocities(cstate: &mut CurrentState, commands: &Vec<Command>) {
-    let mut ncount = 0;
-    let mut thrust_ids = Vec::new();
     for c in commands {
         match c {
             Command::Thrust(shipnum, delta) => {
-                if (delta.lmax_distance() == 0) {
-                    panic!("Thrust(0,0) cannot be chosen in alien GUI")
-                };
-                if thrust_ids.iter().any(|x| *x == shipnum) {
-                    panic!("Multiple thrusts from same id");
-                };
-                thrust_ids.push(shipnum);
                 for (m, actionresult) in &mut cstate.machines {
                     if m.machine_id != (*shipnum as isize) {
                         continue;
@@ -332,6 +328,17 @@ fn state_update_kill_gravity(cstate: &mut CurrentState) {
     }
 }
 
+fn state_update_kill_out_of_stage(cstate: &mut CurrentState) {
+    if let Some(obs) = cstate.obstacle {
+        for m in &mut cstate.machines {
+            if m.0.position.lmax_distance() > obs.stage_half_size as isize {
+                // flew out of the stage
+                m.0.params = Param::default();
+            }
+        }
+    }
+}
+
 fn state_clone_clear_actions(cstate: &CurrentState) -> CurrentState {
     let mut newstate = cstate.clone();
     for m in &mut newstate.machines {
@@ -377,7 +384,10 @@ fn is_dead(m: &Machine) -> bool {
         };
 }
 
-fn get_current_gamestate(cstate: &CurrentState) -> (CurrentGameState, Option<Role>) {
+fn get_current_gamestate(
+    stage: &StageData,
+    cstate: &CurrentState,
+) -> (CurrentGameState, Option<Role>) {
     let mut defender_alive = false;
     let mut attacker_alive = false;
     for mpair in &cstate.machines {
@@ -399,34 +409,89 @@ fn get_current_gamestate(cstate: &CurrentState) -> (CurrentGameState, Option<Rol
                 Role::ATTACKER
             }),
         )
+    } else if cstate.turn >= stage.total_turns {
+        // The defender wins if it survives until the turn limit.
+        (CurrentGameState::END, Some(Role::DEFENDER))
     } else {
         (CurrentGameState::PLAYING, None)
     }
 }
 
-pub fn get_winner(cstate: &CurrentState) -> Option<Role> {
-    get_current_gamestate(cstate).1
+pub fn get_winner(stage: &StageData, cstate: &CurrentState) -> Option<Role> {
+    get_current_gamestate(stage, cstate).1
+}
+
+// Validates the commands of both players against the rules.
+fn validate_all_commands(
+    stage: &StageData,
+    cstate: &CurrentState,
+    commands: &[Command],
+) -> Result<(), Vec<CommandError>> {
+    let mut errors = Vec::new();
+    for &role in &[Role::ATTACKER, Role::DEFENDER] {
+        // Commands for unknown machines are reported on the attacker side.
+        let (indices, role_commands): (Vec<usize>, Vec<Command>) = commands
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| {
+                get_machine_by_id(cstate, c.ship_num()).map_or(Role::ATTACKER, |m| m.role) == role
+            })
+            .map(|(i, c)| (i, *c))
+            .unzip();
+        if let Err(errs) = validate_commands(stage, cstate, role, &role_commands) {
+            errors.extend(errs.into_iter().map(|e| CommandError {
+                index: indices[e.index],
+                ..e
+            }));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        errors.sort_by_key(|e| e.index);
+        Err(errors)
+    }
 }
 
-/* Accepts CurrentState and Commands and outputs updated states. */
+/* Accepts CurrentState and Commands and outputs updated states.
+ * Returns the rule violations instead if any command is illegal. */
 pub fn state_update(
+    stage: &StageData,
     cstate: &CurrentState,
     commands: &Vec<Command>,
-) -> (CurrentGameState, CurrentState) {
+) -> Result<(CurrentGameState, CurrentState), Vec<CommandError>> {
+    validate_all_commands(stage, cstate, commands)?;
     let mut cstate = state_clone_clear_actions(cstate);
+    cstate.turn += 1;
     state_update_obstacles(&mut cstate);
     state_update_velocities(&mut cstate, commands);
     state_update_coordinates(&mut cstate);
     state_update_damages(&mut cstate, commands);
+    state_update_splits(&mut cstate, commands);
     state_update_cooldown(&mut cstate);
     state_update_kill_gravity(&mut cstate);
-    (get_current_gamestate(&cstate).0, cstate)
+    state_update_kill_out_of_stage(&mut cstate);
+    Ok((get_current_gamestate(stage, &cstate).0, cstate))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn stage_data(obstacle: Option<Obstacle>) -> StageData {
+        StageData {
+            total_turns: 256,
+            self_role: Role::ATTACKER,
+            initialize_param: InitializeParam {
+                total_cost: 512,
+                thrust_limit: 1,
+                heat_limit: 64,
+            },
+            obstacle,
+            defender: None,
+        }
+    }
+
     #[test]
     fn test_update() {
         let curstate = {
@@ -468,7 +533,8 @@ mod tests {
         };
         //println!("{:?}", curstate);
         let cmd1 = Command::Thrust(0, Point { x: -1, y: 0 });
-        let (status, updated) = state_update(&curstate, &vec![cmd1]);
+        let stage = stage_data(curstate.obstacle);
+        let (status, updated) = state_update(&stage, &curstate, &vec![cmd1]).unwrap();
         //println!("{:?}", updated);
         // machine 1 should be unchanged
         assert_eq!(updated.machines[0].0.position, Point { x: 33, y: 6 });
@@ -535,10 +601,13 @@ mod tests {
 
         //println!("{:?}", curstate);
         // step 1
+        let stage = stage_data(curstate.obstacle);
         let (status, updated) = state_update(
+            &stage,
             &curstate,
             &vec![Command::Beam(0, Point { x: -20, y: 40 }, 64)],
-        );
+        )
+        .unwrap();
         //println!("{:?}", updated);
         // machine 1 position
         assert_eq!(updated.machines[0].0.position, Point { x: 27, y: -9 });
@@ -560,9 +629,11 @@ mod tests {
 
         // step 2
         let (status, updated) = state_update(
+            &stage,
             &updated,
             &vec![Command::Beam(0, Point { x: -34, y: 28 }, 28)],
-        );
+        )
+        .unwrap();
         // machine 1 position
         assert_eq!(updated.machines[0].0.position, Point { x: 23, y: -12 });
         assert_eq!(updated.machines[0].0.velocity, Point { x: -4, y: -3 });
@@ -641,10 +712,13 @@ mod tests {
             }
         };
         // step 1
+        let stage = stage_data(curstate.obstacle);
         let (status, updated) = state_update(
+            &stage,
             &curstate,
             &vec![Command::Beam(1, Point { x: -36, y: -1 }, 88)],
-        );
+        )
+        .unwrap();
         //println!("{:?}", updated);
         // machine 1 position
         assert_eq!(updated.machines[0].0.velocity, Point { x: 1, y: 4 });
@@ -665,6 +739,395 @@ mod tests {
         assert_eq!(updated.machines[1].0.heat, 120);
     }
 
+    // The state of test_laser_kill, loaded from JSON.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_fixture() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/laser_kill.json");
+        let json = std::fs::read_to_string(path).unwrap();
+        let curstate: CurrentState = serde_json::from_str(&json).unwrap();
+        let stage = stage_data(curstate.obstacle);
+        let (_, updated) = state_update(
+            &stage,
+            &curstate,
+            &vec![Command::Beam(1, Point { x: -36, y: -1 }, 88)],
+        )
+        .unwrap();
+        assert_eq!(updated.machines[0].0.position, Point { x: 36, y: -1 });
+        assert_eq!(updated.machines[1].0.heat, 120);
+    }
+
+    #[test]
+    fn test_split() {
+        let curstate = {
+            let machine1 = Machine {
+                role: Role::DEFENDER,
+                machine_id: 0,
+                position: Point { x: -48, y: 30 },
+                velocity: Point { x: 0, y: 0 },
+                params: Param {
+                    energy: 150,
+                    laser_power: 0,
+                    cool_down_per_turn: 10,
+                    life: 1,
+                },
+                heat: 0,
+                heat_limit: 64,
+                move_limit: 1,
+            };
+            let machine2 = Machine {
+                role: Role::ATTACKER,
+                machine_id: 1,
+                position: Point { x: 48, y: -30 },
+                velocity: Point { x: 0, y: 0 },
+                params: Param {
+                    energy: 326,
+                    laser_power: 0,
+                    cool_down_per_turn: 10,
+                    life: 8,
+                },
+                heat: 8,
+                heat_limit: 64,
+                move_limit: 1,
+            };
+            CurrentState {
+                turn: 0,
+                obstacle: Some(Obstacle {
+                    gravity_radius: 16,
+                    stage_half_size: 128,
+                }),
+                machines: vec![(machine1, vec![]), (machine2, vec![])],
+            }
+        };
+        // step 1: split half of the attacker off.
+        let child_params = Param {
+            energy: 163,
+            laser_power: 0,
+            cool_down_per_turn: 0,
+            life: 3,
+        };
+        let stage = stage_data(curstate.obstacle);
+        let (status, updated) =
+            state_update(&stage, &curstate, &vec![Command::Split(1, child_params)]).unwrap();
+        assert_eq!(status, CurrentGameState::PLAYING);
+        assert_eq!(updated.machines.len(), 3);
+        assert_eq!(updated.machines[1].0.position, Point { x: 47, y: -30 });
+        assert_eq!(updated.machines[1].0.velocity, Point { x: -1, y: 0 });
+        assert_eq!(
+            updated.machines[1].0.params,
+            Param {
+                energy: 163,
+                laser_power: 0,
+                cool_down_per_turn: 10,
+                life: 5
+            }
+        );
+        assert_eq!(
+            updated.machines[1].1,
+            vec![ActionResult::Split {
+                params: child_params
+            }]
+        );
+        let child = updated.machines[2].0;
+        assert_eq!(child.role, Role::ATTACKER);
+        assert_eq!(child.machine_id, 2);
+        assert_eq!(child.position, Point { x: 47, y: -30 });
+        assert_eq!(child.velocity, Point { x: -1, y: 0 });
+        assert_eq!(child.params, child_params);
+        assert_eq!(child.heat, 0);
+        assert_eq!(child.heat_limit, 64);
+        assert_eq!(child.move_limit, 1);
+        assert!(updated.machines[2].1.is_empty());
+
+        // step 2: the parent thrusts away while splitting again.
+        let (status, updated) = state_update(
+            &stage,
+            &updated,
+            &vec![
+                Command::Thrust(1, Point { x: 1, y: 0 }),
+                Command::Split(
+                    1,
+                    Param {
+                        energy: 0,
+                        laser_power: 0,
+                        cool_down_per_turn: 0,
+                        life: 1,
+                    },
+                ),
+            ],
+        )
+        .unwrap();
+        assert_eq!(status, CurrentGameState::PLAYING);
+        assert_eq!(updated.machines.len(), 4);
+        assert_eq!(updated.machines[1].0.position, Point { x: 44, y: -30 });
+        assert_eq!(updated.machines[1].0.velocity, Point { x: -3, y: 0 });
+        assert_eq!(updated.machines[1].0.params.energy, 162);
+        assert_eq!(updated.machines[1].0.params.life, 4);
+        // The child never thrusted and follows the gravity only.
+        assert_eq!(updated.machines[2].0.position, Point { x: 45, y: -30 });
+        assert_eq!(updated.machines[2].0.velocity, Point { x: -2, y: 0 });
+        let grandchild = updated.machines[3].0;
+        assert_eq!(grandchild.machine_id, 3);
+        assert_eq!(grandchild.position, Point { x: 44, y: -30 });
+        assert_eq!(grandchild.velocity, Point { x: -3, y: 0 });
+    }
+
+    #[test]
+    fn test_split_rejected() {
+        let machine = Machine {
+            role: Role::ATTACKER,
+            machine_id: 0,
+            position: Point { x: 48, y: 0 },
+            velocity: Point { x: 0, y: 0 },
+            params: Param {
+                energy: 10,
+                laser_power: 0,
+                cool_down_per_turn: 8,
+                life: 1,
+            },
+            heat: 0,
+            heat_limit: 64,
+            move_limit: 1,
+        };
+        let defender = Machine {
+            role: Role::DEFENDER,
+            machine_id: 1,
+            position: Point { x: -48, y: 0 },
+            ..machine
+        };
+        let curstate = CurrentState {
+            turn: 0,
+            obstacle: None,
+            machines: vec![(machine, vec![]), (defender, vec![])],
+        };
+        // The only remaining life cannot be handed over.
+        let split = Command::Split(
+            0,
+            Param {
+                energy: 5,
+                laser_power: 0,
+                cool_down_per_turn: 0,
+                life: 1,
+            },
+        );
+        let stage = stage_data(curstate.obstacle);
+        let errors = state_update(&stage, &curstate, &vec![split]).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![CommandError {
+                index: 0,
+                command: split,
+                kind: CommandErrorKind::InvalidSplit,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_invalid_thrust() {
+        let attacker = Machine {
+            role: Role::ATTACKER,
+            machine_id: 0,
+            position: Point { x: 48, y: 0 },
+            velocity: Point { x: 0, y: 0 },
+            params: Param {
+                energy: 10,
+                laser_power: 0,
+                cool_down_per_turn: 8,
+                life: 1,
+            },
+            heat: 0,
+            heat_limit: 64,
+            move_limit: 1,
+        };
+        let defender = Machine {
+            role: Role::DEFENDER,
+            machine_id: 1,
+            position: Point { x: -48, y: 0 },
+            ..attacker
+        };
+        let curstate = CurrentState {
+            turn: 0,
+            obstacle: None,
+            machines: vec![(attacker, vec![]), (defender, vec![])],
+        };
+        let stage = stage_data(curstate.obstacle);
+        let commands = vec![
+            Command::Thrust(1, Point { x: 1, y: 0 }),
+            Command::Thrust(0, Point { x: 0, y: 0 }),
+            Command::Thrust(1, Point { x: 0, y: 1 }),
+        ];
+        let errors = state_update(&stage, &curstate, &commands).unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| (e.index, e.kind)).collect::<Vec<_>>(),
+            vec![
+                (1, CommandErrorKind::ZeroThrust),
+                (2, CommandErrorKind::DuplicateThrust)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_out_of_stage() {
+        let attacker = Machine {
+            role: Role::ATTACKER,
+            machine_id: 0,
+            position: Point { x: 127, y: 40 },
+            velocity: Point { x: 3, y: 0 },
+            params: Param {
+                energy: 10,
+                laser_power: 0,
+                cool_down_per_turn: 8,
+                life: 1,
+            },
+            heat: 0,
+            heat_limit: 64,
+            move_limit: 1,
+        };
+        let defender = Machine {
+            role: Role::DEFENDER,
+            machine_id: 1,
+            position: Point { x: -40, y: -127 },
+            velocity: Point { x: 0, y: 1 },
+            ..attacker
+        };
+        let curstate = CurrentState {
+            turn: 10,
+            obstacle: Some(Obstacle {
+                gravity_radius: 16,
+                stage_half_size: 128,
+            }),
+            machines: vec![(attacker, vec![]), (defender, vec![])],
+        };
+        let stage = stage_data(curstate.obstacle);
+        let (status, updated) = state_update(&stage, &curstate, &vec![]).unwrap();
+        assert_eq!(updated.turn, 11);
+        // The attacker left the stage; the defender is pulled back in.
+        assert_eq!(updated.machines[0].0.position, Point { x: 129, y: 40 });
+        assert_eq!(updated.machines[0].0.params, Param::default());
+        assert_eq!(updated.machines[1].0.position, Point { x: -40, y: -125 });
+        assert_eq!(updated.machines[1].0.params, defender.params);
+        assert_eq!(status, CurrentGameState::END);
+        assert_eq!(get_winner(&stage, &updated), Some(Role::DEFENDER));
+    }
+
+    #[test]
+    fn test_turn_limit() {
+        let attacker = Machine {
+            role: Role::ATTACKER,
+            machine_id: 0,
+            position: Point { x: 48, y: 0 },
+            velocity: Point { x: 0, y: 4 },
+            params: Param {
+                energy: 10,
+                laser_power: 0,
+                cool_down_per_turn: 8,
+                life: 1,
+            },
+            heat: 0,
+            heat_limit: 64,
+            move_limit: 1,
+        };
+        let defender = Machine {
+            role: Role::DEFENDER,
+            machine_id: 1,
+            position: Point { x: -48, y: 0 },
+            velocity: Point { x: 0, y: -4 },
+            ..attacker
+        };
+        let curstate = CurrentState {
+            turn: 254,
+            obstacle: Some(Obstacle {
+                gravity_radius: 16,
+                stage_half_size: 128,
+            }),
+            machines: vec![(attacker, vec![]), (defender, vec![])],
+        };
+        let stage = stage_data(curstate.obstacle);
+        let (status, updated) = state_update(&stage, &curstate, &vec![]).unwrap();
+        assert_eq!(updated.turn, 255);
+        assert_eq!(status, CurrentGameState::PLAYING);
+        assert_eq!(get_winner(&stage, &updated), None);
+
+        let (status, updated) = state_update(&stage, &updated, &vec![]).unwrap();
+        assert_eq!(updated.turn, 256);
+        assert_eq!(status, CurrentGameState::END);
+        assert_eq!(get_winner(&stage, &updated), Some(Role::DEFENDER));
+    }
+
+    #[test]
+    fn test_laser_corpus() {
+        let mut checked = 0;
+        for line in include_str!("../testdata/laser.txt").lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let v = line
+                .split_whitespace()
+                .map(|x| x.parse::<isize>().unwrap())
+                .collect::<Vec<_>>();
+            let (shooter, target, power, intensity, unknown) = (
+                Point::new(v[0], v[1]),
+                Point::new(v[2], v[3]),
+                v[4],
+                v[5],
+                v[6],
+            );
+            assert_eq!(
+                get_intensity(&(target - shooter), power as usize) as isize,
+                intensity,
+                "{}",
+                line
+            );
+
+            let attacker = Machine {
+                role: Role::ATTACKER,
+                machine_id: 0,
+                position: shooter,
+                velocity: Point { x: 0, y: 0 },
+                params: Param {
+                    energy: 0,
+                    laser_power: power as usize,
+                    cool_down_per_turn: 0,
+                    life: 1,
+                },
+                heat: 0,
+                heat_limit: 1024,
+                move_limit: 1,
+            };
+            let defender = Machine {
+                role: Role::DEFENDER,
+                machine_id: 1,
+                position: Point { x: 1000, y: 1000 },
+                ..attacker
+            };
+            let curstate = CurrentState {
+                turn: 0,
+                obstacle: None,
+                machines: vec![(attacker, vec![]), (defender, vec![])],
+            };
+            let stage = stage_data(curstate.obstacle);
+            let (_, updated) =
+                state_update(&stage, &curstate, &vec![Command::Beam(0, target, power)]).unwrap();
+            match updated.machines[0].1.as_slice() {
+                [ActionResult::Laser {
+                    opponent,
+                    power: p,
+                    intensity: i,
+                    _3,
+                }] => {
+                    assert_eq!(*opponent, target, "{}", line);
+                    assert_eq!(*p as isize, power, "{}", line);
+                    assert_eq!(*i as isize, intensity, "{}", line);
+                    assert_eq!(*_3, unknown, "{}", line);
+                }
+                results => panic!("{}: unexpected results {:?}", line, results),
+            }
+            checked += 1;
+        }
+        assert!(checked > 0, "testdata/laser.txt has no observations");
+    }
+
     #[test]
     fn power_table_test() {
         assert_eq!(lookup_destruct_power_table(1), 128);
@@ -0,0 +1,205 @@
+// Copyright 2020 Google LLC
+// Copyright 2020 Team Spacecat
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+// Delivers a modulated request to the game server and returns the modulated
+// response.
+pub trait Transport {
+    fn send(&mut self, request: &str) -> Result<String>;
+}
+
+// Talks to infra/interact/interact.py through stdout/stdin.
+pub struct StdioTransport;
+
+impl Transport for StdioTransport {
+    fn send(&mut self, request: &str) -> Result<String> {
+        println!("{}", request);
+        io::stdout().flush()?;
+        let mut resp = String::new();
+        if io::stdin().read_line(&mut resp)? == 0 {
+            bail!("stdin is closed");
+        }
+        Ok(resp.trim().to_string())
+    }
+}
+
+// POSTs to `<server_url>/aliens/send` directly. Only plain http is supported,
+// which is enough for infra/local_server.
+pub struct HttpTransport {
+    host: String,
+    path: String,
+}
+
+impl HttpTransport {
+    pub fn new(server_url: &str) -> Result<HttpTransport> {
+        const SCHEME: &str = "http://";
+        if !server_url.starts_with(SCHEME) {
+            bail!("unsupported server url: {}", server_url);
+        }
+        let rest = &server_url[SCHEME.len()..];
+        let (authority, query) = match rest.find('?') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, ""),
+        };
+        let (host, prefix) = match authority.find('/') {
+            Some(i) => (&authority[..i], authority[i..].trim_end_matches('/')),
+            None => (authority, ""),
+        };
+        let host = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+        Ok(HttpTransport {
+            host,
+            path: format!("{}/aliens/send{}", prefix, query),
+        })
+    }
+}
+
+impl Transport for HttpTransport {
+    fn send(&mut self, request: &str) -> Result<String> {
+        let mut stream = TcpStream::connect(&self.host)
+            .with_context(|| format!("failed to connect to {}", self.host))?;
+        // HTTP/1.0 lets us read the response until the server closes it.
+        write!(
+            stream,
+            "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+            self.path,
+            self.host,
+            request.len(),
+            request
+        )?;
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp)?;
+        let (head, body) = match resp.find("\r\n\r\n") {
+            Some(i) => (&resp[..i], &resp[i + 4..]),
+            None => bail!("malformed http response: {:?}", resp),
+        };
+        let status = head.lines().next().unwrap_or("");
+        if status.split_whitespace().nth(1) != Some("200") {
+            bail!("non-200 response: {}", status);
+        }
+        Ok(body.trim().to_string())
+    }
+}
+
+// In-process transport, e.g. for unit tests or a tournament harness.
+// The requests arrive at the paired ChannelPeer.
+pub struct ChannelTransport {
+    requests: Sender<String>,
+    responses: Receiver<String>,
+}
+
+pub struct ChannelPeer {
+    pub requests: Receiver<String>,
+    pub responses: Sender<String>,
+}
+
+impl ChannelPeer {
+    // Answers requests with `f` until the ChannelTransport is dropped.
+    pub fn serve(&self, mut f: impl FnMut(&str) -> String) {
+        for req in self.requests.iter() {
+            if self.responses.send(f(&req)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+pub fn channel() -> (ChannelTransport, ChannelPeer) {
+    let (req_tx, req_rx) = mpsc::channel();
+    let (resp_tx, resp_rx) = mpsc::channel();
+    (
+        ChannelTransport {
+            requests: req_tx,
+            responses: resp_rx,
+        },
+        ChannelPeer {
+            requests: req_rx,
+            responses: resp_tx,
+        },
+    )
+}
+
+impl Transport for ChannelTransport {
+    fn send(&mut self, request: &str) -> Result<String> {
+        self.requests
+            .send(request.to_string())
+            .map_err(|_| anyhow!("channel peer is gone"))?;
+        self.responses
+            .recv()
+            .map_err(|_| anyhow!("channel peer is gone"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufRead;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_channel() {
+        let (mut transport, peer) = channel();
+        let t = thread::spawn(move || peer.serve(|req| format!("{}0", req)));
+        assert_eq!(transport.send("1").unwrap(), "10");
+        assert_eq!(transport.send("11").unwrap(), "110");
+        drop(transport);
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/?apiKey=xyz", listener.local_addr().unwrap());
+        let t = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = io::BufReader::new(stream);
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "POST /aliens/send?apiKey=xyz HTTP/1.0\r\n");
+            let mut len = 0;
+            loop {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                let mut header = line.splitn(2, ':');
+                if header.next() == Some("Content-Length") {
+                    len = header.next().unwrap().trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; len];
+            reader.read_exact(&mut body).unwrap();
+            assert_eq!(body, b"1101000");
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.0 200 OK\r\nContent-Length: 8\r\n\r\n1101000\n")
+                .unwrap();
+        });
+        let mut transport = HttpTransport::new(&url).unwrap();
+        assert_eq!(transport.send("1101000").unwrap(), "1101000");
+        t.join().unwrap();
+
+        assert!(HttpTransport::new("https://example.com").is_err());
+    }
+}
@@ -13,49 +13,367 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
+use num_bigint::{BigInt, BigUint, Sign};
+use std::convert::{TryFrom, TryInto};
+use std::io::{self, BufReader, Read};
 
-#[derive(Clone, Debug)]
+// Clone, Drop, Debug and Display are written by hand below. Galaxy payloads
+// can nest millions of cells deep, so nothing here may recurse per cell.
 pub enum Value {
     Int(i128),
+    // Only for integers that do not fit in Int. See Value::from_big_int.
+    BigInt(BigInt),
     Nil,
     Cons(Box<Value>, Box<Value>),
 }
 
-fn is_list(val: &Value) -> bool {
-    match val {
-        Value::Int(_) => false,
-        Value::Nil => true,
-        Value::Cons(_, cdr) => is_list(cdr),
+impl Value {
+    pub fn from_big_int(n: BigInt) -> Value {
+        match i128::try_from(&n) {
+            Ok(n) => Value::Int(n),
+            Err(_) => Value::BigInt(n),
+        }
     }
-}
 
-impl ToString for Value {
-    fn to_string(&self) -> String {
+    // Clones everything except the cells.
+    fn clone_leaf(&self) -> Value {
         match self {
-            &Value::Int(n) => format!("{}", n),
-            Value::Nil => format!("nil"),
-            Value::Cons(x, y) => {
-                if is_list(self) {
-                    "(".to_string()
-                        + &to_vec(self.clone())
-                            .unwrap()
-                            .into_iter()
-                            .map(|val| val.to_string())
-                            .collect::<Vec<String>>()
-                            .join(" ")
-                        + ")"
-                } else {
-                    format!("({} . {})", x.to_string(), y.to_string())
+            &Value::Int(n) => Value::Int(n),
+            Value::BigInt(n) => Value::BigInt(n.clone()),
+            Value::Nil | Value::Cons(_, _) => Value::Nil,
+        }
+    }
+}
+
+impl Clone for Value {
+    fn clone(&self) -> Value {
+        enum Step<'a> {
+            Visit(&'a Value),
+            Build,
+        }
+        let mut steps = vec![Step::Visit(self)];
+        let mut built = vec![];
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Visit(Value::Cons(car, cdr)) => {
+                    steps.push(Step::Build);
+                    steps.push(Step::Visit(cdr));
+                    steps.push(Step::Visit(car));
                 }
+                Step::Visit(leaf) => built.push(leaf.clone_leaf()),
+                Step::Build => {
+                    let cdr = built.pop().unwrap();
+                    let car = built.pop().unwrap();
+                    built.push(Value::Cons(Box::new(car), Box::new(cdr)));
+                }
+            }
+        }
+        built.pop().unwrap()
+    }
+}
+
+// Moves the cells out of `val` so that dropping it does not recurse.
+fn take_cells(val: &mut Value, cells: &mut Vec<Value>) {
+    if let Value::Cons(car, cdr) = val {
+        for child in [car, cdr].iter_mut() {
+            if let Value::Cons(_, _) = ***child {
+                cells.push(std::mem::replace(&mut ***child, Value::Nil));
             }
         }
     }
 }
 
+impl Drop for Value {
+    fn drop(&mut self) {
+        let mut cells = vec![];
+        take_cells(self, &mut cells);
+        while let Some(mut cell) = cells.pop() {
+            take_cells(&mut cell, &mut cells);
+        }
+    }
+}
+
+pub(crate) fn is_list(val: &Value) -> bool {
+    let mut val = val;
+    loop {
+        match val {
+            Value::Int(_) | Value::BigInt(_) => return false,
+            Value::Nil => return true,
+            Value::Cons(_, cdr) => val = cdr,
+        }
+    }
+}
+
+enum Token<'a> {
+    Val(&'a Value),
+    Num(&'a dyn std::fmt::Display),
+    Str(&'static str),
+}
+
+// Prints `val` in the notation given by `expand`, which replaces a value with
+// the tokens to print, pushed in reverse order.
+fn print<'a>(
+    f: &mut std::fmt::Formatter,
+    val: &'a Value,
+    expand: impl Fn(&'a Value, &mut Vec<Token<'a>>),
+) -> std::fmt::Result {
+    let mut tokens = vec![Token::Val(val)];
+    while let Some(t) = tokens.pop() {
+        match t {
+            Token::Val(val) => expand(val, &mut tokens),
+            Token::Num(n) => write!(f, "{}", n)?,
+            Token::Str(s) => f.write_str(s)?,
+        }
+    }
+    Ok(())
+}
+
+// (1 2 3) for lists, (1 . 2) otherwise.
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        print(f, self, |val, tokens| match val {
+            Value::Int(n) => tokens.push(Token::Num(n)),
+            Value::BigInt(n) => tokens.push(Token::Num(n)),
+            Value::Nil => tokens.push(Token::Str("nil")),
+            Value::Cons(car, cdr) if is_list(val) => {
+                let mut items = vec![&**car];
+                let mut rest = &**cdr;
+                while let Value::Cons(car, cdr) = rest {
+                    items.push(car);
+                    rest = cdr;
+                }
+                tokens.push(Token::Str(")"));
+                for (i, item) in items.into_iter().enumerate().rev() {
+                    tokens.push(Token::Val(item));
+                    if i > 0 {
+                        tokens.push(Token::Str(" "));
+                    }
+                }
+                tokens.push(Token::Str("("));
+            }
+            Value::Cons(car, cdr) => {
+                tokens.push(Token::Str(")"));
+                tokens.push(Token::Val(cdr));
+                tokens.push(Token::Str(" . "));
+                tokens.push(Token::Val(car));
+                tokens.push(Token::Str("("));
+            }
+        })
+    }
+}
+
+// The same as #[derive(Debug)], e.g. Cons(Int(1), Nil).
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        print(f, self, |val, tokens| match val {
+            Value::Int(n) => {
+                tokens.push(Token::Str(")"));
+                tokens.push(Token::Num(n));
+                tokens.push(Token::Str("Int("));
+            }
+            Value::BigInt(n) => {
+                tokens.push(Token::Str(")"));
+                tokens.push(Token::Num(n));
+                tokens.push(Token::Str("BigInt("));
+            }
+            Value::Nil => tokens.push(Token::Str("Nil")),
+            Value::Cons(car, cdr) => {
+                tokens.push(Token::Str(")"));
+                tokens.push(Token::Val(cdr));
+                tokens.push(Token::Str(", "));
+                tokens.push(Token::Val(car));
+                tokens.push(Token::Str("Cons("));
+            }
+        })
+    }
+}
+
+// `ap ap cons 1 nil` notation, as in galaxy.txt.
+struct Ap<'a>(&'a Value);
+
+impl std::fmt::Display for Ap<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        print(f, self.0, |val, tokens| match val {
+            Value::Int(n) => tokens.push(Token::Num(n)),
+            Value::BigInt(n) => tokens.push(Token::Num(n)),
+            Value::Nil => tokens.push(Token::Str("nil")),
+            Value::Cons(car, cdr) => {
+                tokens.push(Token::Val(cdr));
+                tokens.push(Token::Str(" "));
+                tokens.push(Token::Val(car));
+                tokens.push(Token::Str("ap ap cons "));
+            }
+        })
+    }
+}
+
+impl Value {
+    pub fn to_ap(&self) -> String {
+        Ap(self).to_string()
+    }
+}
+
+// A partially parsed `ap` term.
+enum Term {
+    Val(Value),
+    Cons,
+    Cons1(Value),
+}
+
+enum Frame {
+    // Waits for the function, then for the argument.
+    Ap(Option<Term>),
+    List {
+        close: &'static str,
+        items: Vec<Value>,
+        // Set by '.', and then filled by the tail.
+        dotted: bool,
+        tail: Option<Value>,
+    },
+}
+
+fn tokenize(s: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut start = None;
+    for (i, c) in s.char_indices() {
+        let delimiter = "()[],.".contains(c);
+        if c.is_whitespace() || delimiter {
+            if let Some(st) = start.take() {
+                tokens.push(&s[st..i]);
+            }
+            if delimiter {
+                tokens.push(&s[i..i + 1]);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(st) = start {
+        tokens.push(&s[st..]);
+    }
+    tokens
+}
+
+// Parses integers, `nil`, lists like `(1 2 3)`, `(1, 2, 3)` or `[1, 2, 3]`,
+// dotted pairs like `(1 2 . 3)` and `ap ap cons 1 nil`, in any combination.
+// Iterative like everything else here.
+impl std::str::FromStr for Value {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Value> {
+        let mut stack: Vec<Frame> = vec![];
+        let mut result = None;
+        for (i, &token) in tokenize(s).iter().enumerate() {
+            if result.is_some() {
+                bail!("token {}: trailing input: {}", i, token);
+            }
+            let mut term = match token {
+                "ap" => {
+                    stack.push(Frame::Ap(None));
+                    continue;
+                }
+                "(" | "[" => {
+                    stack.push(Frame::List {
+                        close: if token == "(" { ")" } else { "]" },
+                        items: vec![],
+                        dotted: false,
+                        tail: None,
+                    });
+                    continue;
+                }
+                "," => match stack.last() {
+                    Some(Frame::List { dotted: false, .. }) => continue,
+                    _ => bail!("token {}: unexpected ','", i),
+                },
+                "." => match stack.last_mut() {
+                    Some(Frame::List { items, dotted, .. }) if !items.is_empty() && !*dotted => {
+                        *dotted = true;
+                        continue;
+                    }
+                    _ => bail!("token {}: unexpected '.'", i),
+                },
+                ")" | "]" => match stack.pop() {
+                    Some(Frame::List {
+                        close,
+                        items,
+                        dotted,
+                        tail,
+                    }) if close == token && dotted == tail.is_some() => {
+                        let tail = tail.unwrap_or(Value::Nil);
+                        Term::Val(
+                            items
+                                .into_iter()
+                                .rev()
+                                .fold(tail, |acc, x| Value::Cons(Box::new(x), Box::new(acc))),
+                        )
+                    }
+                    _ => bail!("token {}: unexpected '{}'", i, token),
+                },
+                "nil" => Term::Val(Value::Nil),
+                "cons" | "vec" => Term::Cons,
+                _ => match token.parse::<BigInt>() {
+                    Ok(n) => Term::Val(Value::from_big_int(n)),
+                    Err(_) => bail!("token {}: unknown token: {}", i, token),
+                },
+            };
+            // Feed the term to the innermost frame, applying finished `ap`s.
+            loop {
+                match stack.last_mut() {
+                    None => {
+                        result = Some(term);
+                        break;
+                    }
+                    Some(Frame::Ap(f @ None)) => {
+                        *f = Some(term);
+                        break;
+                    }
+                    Some(Frame::Ap(Some(_))) => {
+                        let f = match stack.pop() {
+                            Some(Frame::Ap(Some(f))) => f,
+                            _ => unreachable!(),
+                        };
+                        term = match (f, term) {
+                            (Term::Cons, Term::Val(x)) => Term::Cons1(x),
+                            (Term::Cons1(x), Term::Val(y)) => {
+                                Term::Val(Value::Cons(Box::new(x), Box::new(y)))
+                            }
+                            _ => bail!("token {}: cannot apply", i),
+                        };
+                    }
+                    Some(Frame::List {
+                        dotted,
+                        tail,
+                        items,
+                        ..
+                    }) => {
+                        let val = match term {
+                            Term::Val(val) => val,
+                            _ => bail!("token {}: partial application in a list", i),
+                        };
+                        if !*dotted {
+                            items.push(val);
+                        } else if tail.is_none() {
+                            *tail = Some(val);
+                        } else {
+                            bail!("token {}: more than one value after '.'", i);
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+        match (result, stack.is_empty()) {
+            (Some(Term::Val(val)), true) => Ok(val),
+            (Some(_), true) => bail!("partial application"),
+            _ => bail!("unexpected end of input"),
+        }
+    }
+}
+
 pub fn modulate_to_string(val: &Value) -> String {
     let mut v = vec![];
-    modulate(&val, &mut v);
+    modulate(val, &mut v);
     v.iter().map(|x| if *x { "1" } else { "0" }).collect()
 }
 
@@ -64,85 +382,344 @@ pub fn demodulate_from_string(s: &str) -> Option<Value> {
     demodulate(&mut vb.into_iter())
 }
 
-pub fn modulate(val: &Value, v: &mut Vec<bool>) {
-    match val {
-        &Value::Int(n) => {
-            let n = if n >= 0 {
-                v.push(false);
-                v.push(true);
-                n as u64
-            } else {
-                v.push(true);
-                v.push(false);
-                n.abs() as u64
-            };
+// Why a bit string failed to demodulate, with the offset of the bit.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DemodulateError {
+    // A character other than '0' or '1'.
+    InvalidChar(usize),
+    // The input ended in the middle of a value.
+    UnexpectedEnd(usize),
+    // Bits are left after the value.
+    TrailingBits(usize),
+}
 
-            let keta = 64 - n.leading_zeros();
-            let t = (keta + 3) / 4;
+impl DemodulateError {
+    pub fn offset(&self) -> usize {
+        match *self {
+            DemodulateError::InvalidChar(offset)
+            | DemodulateError::UnexpectedEnd(offset)
+            | DemodulateError::TrailingBits(offset) => offset,
+        }
+    }
+}
 
-            for _ in 0..t {
-                v.push(true);
+impl std::fmt::Display for DemodulateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DemodulateError::InvalidChar(offset) => {
+                write!(f, "invalid character at bit {}", offset)
             }
-            v.push(false);
+            DemodulateError::UnexpectedEnd(offset) => write!(f, "unexpected end at bit {}", offset),
+            DemodulateError::TrailingBits(offset) => write!(f, "trailing bits at bit {}", offset),
+        }
+    }
+}
+
+impl std::error::Error for DemodulateError {}
+
+// Like demodulate_from_string, but rejects anything except exactly one value.
+pub fn try_demodulate_from_string(s: &str) -> Result<Value, DemodulateError> {
+    let bits = s
+        .chars()
+        .enumerate()
+        .map(|(i, c)| match c {
+            '0' => Ok(false),
+            '1' => Ok(true),
+            _ => Err(DemodulateError::InvalidChar(i)),
+        })
+        .collect::<Result<Vec<bool>, _>>()?;
+    let mut it = bits.iter().copied();
+    let val = demodulate(&mut it).ok_or(DemodulateError::UnexpectedEnd(bits.len()))?;
+    let rest = it.count();
+    if rest > 0 {
+        return Err(DemodulateError::TrailingBits(bits.len() - rest));
+    }
+    Ok(val)
+}
 
-            for i in (0..4 * t).rev() {
-                v.push((n >> i) & 1 == 1);
+// A failure of a Demodulator.
+#[derive(Debug)]
+pub enum StreamError {
+    Io(io::Error),
+    // `partial` is the value being read, with "_" for the token that failed
+    // and "..." for what was not read yet, e.g. "(1 (2 _ ...) ...)".
+    Invalid {
+        error: DemodulateError,
+        partial: String,
+    },
+}
+
+impl std::fmt::Display for StreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StreamError::Io(e) => write!(f, "{}", e),
+            StreamError::Invalid { error, partial } => write!(f, "{} in {}", error, partial),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+fn render_partial(cells: &[Option<Value>]) -> String {
+    let mut out = String::new();
+    let mut closers = vec![];
+    // Whether the next cell is the tail of the list being printed.
+    let mut tail = false;
+    for cell in cells {
+        match cell {
+            Some(car) => {
+                if !tail {
+                    out.push('(');
+                    closers.push(")");
+                }
+                out.push_str(&car.to_string());
+                out.push(' ');
+                tail = true;
+            }
+            None => {
+                if tail {
+                    closers.pop();
+                } else {
+                    out.push('(');
+                }
+                closers.push(" ...)");
+                tail = false;
             }
         }
-        Value::Nil => {
-            v.push(false);
-            v.push(false);
+    }
+    out.push_str(if tail { ". _" } else { "_" });
+    closers.iter().rev().for_each(|c| out.push_str(c));
+    out
+}
+
+// Reads values from a stream of '0' and '1' characters. Values may be
+// separated by whitespace, e.g. one per line, but nothing else is accepted.
+// Offsets in errors count bits from the start of the stream.
+pub struct Demodulator<R: Read> {
+    bytes: io::Bytes<BufReader<R>>,
+    offset: usize,
+    // The byte read ahead while looking for the next value.
+    peeked: Option<u8>,
+    failed: bool,
+}
+
+impl<'a> Demodulator<&'a [u8]> {
+    pub fn from_bytes(bytes: &'a [u8]) -> Demodulator<&'a [u8]> {
+        Demodulator::new(bytes)
+    }
+}
+
+impl<R: Read> Demodulator<R> {
+    pub fn new(reader: R) -> Demodulator<R> {
+        Demodulator {
+            bytes: BufReader::new(reader).bytes(),
+            offset: 0,
+            peeked: None,
+            failed: false,
         }
-        Value::Cons(hd, tl) => {
-            v.push(true);
-            v.push(true);
-            modulate(hd, v);
-            modulate(tl, v);
+    }
+
+    // The number of bits read so far.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    // Returns None at the end of the stream.
+    pub fn read_value(&mut self) -> std::result::Result<Option<Value>, StreamError> {
+        loop {
+            match self.bytes.next().transpose().map_err(StreamError::Io)? {
+                None => return Ok(None),
+                Some(c) if c.is_ascii_whitespace() => continue,
+                Some(c) => {
+                    self.peeked = Some(c);
+                    break;
+                }
+            }
         }
+        let Demodulator {
+            bytes,
+            offset,
+            peeked,
+            ..
+        } = self;
+        let mut next = || {
+            let c = match peeked.take() {
+                Some(c) => c,
+                None => match bytes.next() {
+                    Some(c) => c.map_err(Err)?,
+                    None => return Err(Ok(DemodulateError::UnexpectedEnd(*offset))),
+                },
+            };
+            let bit = match c {
+                b'0' => false,
+                b'1' => true,
+                _ => return Err(Ok(DemodulateError::InvalidChar(*offset))),
+            };
+            *offset += 1;
+            Ok(bit)
+        };
+        demodulate_with(&mut next)
+            .map(Some)
+            .map_err(|(e, cells)| match e {
+                Ok(error) => StreamError::Invalid {
+                    error,
+                    partial: render_partial(&cells),
+                },
+                Err(e) => StreamError::Io(e),
+            })
     }
 }
 
-pub fn demodulate(it: &mut impl Iterator<Item = bool>) -> Option<Value> {
-    let t0 = it.next()?;
-    let t1 = it.next()?;
+// Stops after the first error.
+impl<R: Read> Iterator for Demodulator<R> {
+    type Item = std::result::Result<Value, StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let res = self.read_value().transpose();
+        self.failed = matches!(res, Some(Err(_)));
+        res
+    }
+}
 
-    Some(match (t0, t1) {
-        (false, false) => Value::Nil,
-        (true, true) => {
-            let x = demodulate(it)?;
-            let y = demodulate(it)?;
-            Value::Cons(Box::new(x), Box::new(y))
+// Writes the sign, the number of nibbles in unary and the nibbles.
+fn modulate_int(negative: bool, nibbles: &[u8], v: &mut Vec<bool>) {
+    v.push(negative);
+    v.push(!negative);
+    for _ in 0..nibbles.len() {
+        v.push(true);
+    }
+    v.push(false);
+    for nibble in nibbles {
+        for i in (0..4).rev() {
+            v.push((nibble >> i) & 1 == 1);
         }
-        (_, y) => {
-            let mut t = 0;
-            while it.next()? {
-                t += 1;
+    }
+}
+
+pub fn modulate(val: &Value, v: &mut Vec<bool>) {
+    let mut stack = vec![val];
+    while let Some(val) = stack.pop() {
+        match val {
+            &Value::Int(n) => {
+                // Wrapping keeps the magnitude of i128::MIN.
+                let abs = if n < 0 {
+                    (n as u128).wrapping_neg()
+                } else {
+                    n as u128
+                };
+                let keta = 128 - abs.leading_zeros();
+                let t = (keta + 3) / 4;
+                let nibbles = (0..t)
+                    .rev()
+                    .map(|i| (abs >> (4 * i)) as u8 & 0xf)
+                    .collect::<Vec<_>>();
+                modulate_int(n < 0, &nibbles, v);
             }
-            let mut v = 0;
-            for i in (0..4 * t).rev() {
-                v |= (if it.next()? { 1 } else { 0 }) << i;
+            Value::BigInt(n) => {
+                modulate_int(n.sign() == Sign::Minus, &n.magnitude().to_radix_be(16), v)
+            }
+            Value::Nil => {
+                v.push(false);
+                v.push(false);
+            }
+            Value::Cons(hd, tl) => {
+                v.push(true);
+                v.push(true);
+                stack.push(tl);
+                stack.push(hd);
             }
-            Value::Int(if y { v } else { -v })
         }
+    }
+}
+
+pub(crate) fn demodulate_int<E>(
+    positive: bool,
+    next: &mut impl FnMut() -> std::result::Result<bool, E>,
+) -> std::result::Result<Value, E> {
+    let mut t = 0;
+    while next()? {
+        t += 1;
+    }
+    let mut nibbles = Vec::with_capacity(t);
+    for _ in 0..t {
+        let mut nibble = 0;
+        for _ in 0..4 {
+            nibble = nibble << 1 | next()? as u8;
+        }
+        nibbles.push(nibble);
+    }
+    // 31 nibbles always fit in i128; longer ones go through BigInt.
+    Ok(if t <= 31 {
+        let v = nibbles.iter().fold(0, |acc, &x| acc << 4 | x as i128);
+        Value::Int(if positive { v } else { -v })
+    } else {
+        let sign = if positive { Sign::Plus } else { Sign::Minus };
+        let abs = BigUint::from_radix_be(&nibbles, 16).expect("nibbles are below 16");
+        Value::from_big_int(BigInt::from_biguint(sign, abs))
     })
 }
 
-// TODO
-// pub fn from_vec(vals: Vec<Value>) -> Value {
-// }
+// Cells being built: None until the car is done, then Some(car).
+type OpenCells = Vec<Option<Value>>;
+
+// Reads one value from `next`. On failure, the cells that were still open
+// are returned along with the error.
+fn demodulate_with<E>(
+    next: &mut impl FnMut() -> std::result::Result<bool, E>,
+) -> std::result::Result<Value, (E, OpenCells)> {
+    let mut cells: OpenCells = vec![];
+    loop {
+        let token = next().and_then(|t0| Ok((t0, next()?)));
+        let mut val = match token {
+            Ok((false, false)) => Ok(Value::Nil),
+            Ok((true, true)) => {
+                cells.push(None);
+                continue;
+            }
+            Ok((_, y)) => demodulate_int(y, next),
+            Err(e) => Err(e),
+        }
+        .map_err(|e| (e, std::mem::take(&mut cells)))?;
+        loop {
+            match cells.pop() {
+                None => return Ok(val),
+                Some(None) => {
+                    cells.push(Some(val));
+                    break;
+                }
+                Some(Some(car)) => val = Value::Cons(Box::new(car), Box::new(val)),
+            }
+        }
+    }
+}
+
+pub fn demodulate(it: &mut impl Iterator<Item = bool>) -> Option<Value> {
+    demodulate_with(&mut || it.next().ok_or(())).ok()
+}
+
+pub fn from_vec(vals: Vec<Value>) -> Value {
+    vals.into_iter()
+        .rev()
+        .fold(Value::Nil, |acc, x| Value::Cons(Box::new(x), Box::new(acc)))
+}
 
 pub fn to_vec(val: Value) -> Result<Vec<Value>> {
     let mut val = val;
     let mut vals = Vec::new();
     loop {
-        match val {
+        // Value implements Drop, so the cells are taken apart by replace.
+        let cdr = match &mut val {
             Value::Cons(car, cdr) => {
-                vals.push(*car);
-                val = *cdr;
+                vals.push(std::mem::replace(&mut **car, Value::Nil));
+                std::mem::replace(&mut **cdr, Value::Nil)
             }
             Value::Nil => break,
-            _ => bail!("unexpected value: {}", val.to_string()),
-        }
+            _ => bail!("unexpected value: {}", val),
+        };
+        val = cdr;
     }
     Ok(vals)
 }
@@ -157,7 +734,117 @@ pub fn to_option(val: Value) -> Option<Value> {
 pub fn to_int(val: &Value) -> Result<i128> {
     match val {
         Value::Int(n) => Ok(*n),
-        _ => bail!("not an integer: {}", val.to_string()),
+        Value::BigInt(n) => bail!("does not fit in i128: {}", n),
+        _ => bail!("not an integer: {}", val),
+    }
+}
+
+pub fn to_big_int(val: &Value) -> Result<BigInt> {
+    match val {
+        Value::Int(n) => Ok(BigInt::from(*n)),
+        Value::BigInt(n) => Ok(n.clone()),
+        _ => bail!("not an integer: {}", val),
+    }
+}
+
+// Converts to the integer type the caller needs, failing instead of wrapping.
+pub fn to_int_as<T: TryFrom<i128>>(val: &Value) -> Result<T> {
+    let n = to_int(val)?;
+    n.try_into()
+        .map_err(|_| anyhow!("does not fit in {}: {}", std::any::type_name::<T>(), n))
+}
+
+// In JSON, integers are numbers (strings when they do not fit in i128),
+// lists are arrays and other cells are {"car": .., "cdr": ..}.
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use super::*;
+    use serde::de::{self, MapAccess, SeqAccess, Visitor};
+    use serde::ser::{SerializeMap, SerializeSeq};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    impl Serialize for Value {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match self {
+                &Value::Int(n) => serializer.serialize_i128(n),
+                Value::BigInt(n) => serializer.serialize_str(&n.to_string()),
+                Value::Cons(car, cdr) if !is_list(self) => {
+                    let mut map = serializer.serialize_map(Some(2))?;
+                    map.serialize_entry("car", car)?;
+                    map.serialize_entry("cdr", cdr)?;
+                    map.end()
+                }
+                _ => {
+                    let mut seq = serializer.serialize_seq(None)?;
+                    let mut val = self;
+                    while let Value::Cons(car, cdr) = val {
+                        seq.serialize_element(car)?;
+                        val = cdr;
+                    }
+                    seq.end()
+                }
+            }
+        }
+    }
+
+    struct ValueVisitor;
+
+    impl<'de> Visitor<'de> for ValueVisitor {
+        type Value = Value;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("an integer, a list or a {car, cdr} pair")
+        }
+
+        fn visit_i64<E: de::Error>(self, n: i64) -> Result<Value, E> {
+            Ok(Value::Int(n.into()))
+        }
+
+        fn visit_u64<E: de::Error>(self, n: u64) -> Result<Value, E> {
+            Ok(Value::Int(n.into()))
+        }
+
+        fn visit_i128<E: de::Error>(self, n: i128) -> Result<Value, E> {
+            Ok(Value::Int(n))
+        }
+
+        fn visit_u128<E: de::Error>(self, n: u128) -> Result<Value, E> {
+            Ok(Value::from_big_int(n.into()))
+        }
+
+        fn visit_str<E: de::Error>(self, s: &str) -> Result<Value, E> {
+            s.parse::<BigInt>()
+                .map(Value::from_big_int)
+                .map_err(|_| E::invalid_value(de::Unexpected::Str(s), &self))
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+            let mut vals = vec![];
+            while let Some(val) = seq.next_element()? {
+                vals.push(val);
+            }
+            Ok(from_vec(vals))
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+            let (mut car, mut cdr) = (None, None);
+            while let Some(key) = map.next_key::<String>()? {
+                match key.as_str() {
+                    "car" => car = Some(map.next_value()?),
+                    "cdr" => cdr = Some(map.next_value()?),
+                    _ => return Err(de::Error::unknown_field(&key, &["car", "cdr"])),
+                }
+            }
+            let car = car.ok_or_else(|| de::Error::missing_field("car"))?;
+            let cdr = cdr.ok_or_else(|| de::Error::missing_field("cdr"))?;
+            Ok(Value::Cons(Box::new(car), Box::new(cdr)))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Value {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Value, D::Error> {
+            deserializer.deserialize_any(ValueVisitor)
+        }
     }
 }
 
@@ -210,4 +897,235 @@ mod tests {
             assert_eq!(&v, bin);
         }
     }
+
+    // The vectors of the old oka modulator, whose "(a, b)" was a cons pair.
+    #[test]
+    fn test_oka_vectors() {
+        for (bin, pairs, list) in [
+            (
+                "110110000111011111100001001111110100110000",
+                "(1 . (81740 . nil))",
+                "(1 81740)",
+            ),
+            ("010", "0", "0"),
+            ("01100001", "1", "1"),
+            ("10100001", "-1", "-1"),
+        ]
+        .iter()
+        {
+            let val: Value = pairs.parse().unwrap();
+            assert_eq!(modulate_to_string(&val), *bin);
+            assert_eq!(demodulate_from_string(bin).unwrap().to_string(), *list);
+        }
+
+        let val: Value = "(1 . (-81740 . nil))".parse().unwrap();
+        let back = demodulate_from_string(&modulate_to_string(&val)).unwrap();
+        assert_eq!(back.to_string(), "(1 -81740)");
+        assert_eq!(back.to_string(), val.to_string());
+    }
+
+    #[test]
+    fn test_big_int() {
+        let big = |s: &str| Value::from_big_int(s.parse::<BigInt>().unwrap());
+        for val in [
+            Value::Int(i128::MAX),
+            Value::Int(i128::MIN),
+            Value::Int(-(1 << 124)),
+            big("170141183460469231731687303715884105728"),
+            big("-1234567890123456789012345678901234567890123456789"),
+        ]
+        .iter()
+        {
+            let bits = modulate_to_string(val);
+            let back = demodulate_from_string(&bits).unwrap();
+            assert_eq!(back.to_string(), val.to_string());
+            assert_eq!(modulate_to_string(&back), bits);
+        }
+        // 2^124 is the first value with 32 nibbles.
+        assert_eq!(
+            modulate_to_string(&Value::Int(1 << 124)),
+            format!("01{}0{}{}", "1".repeat(32), "0001", "0".repeat(124))
+        );
+        // Small values are normalized to Int.
+        assert!(matches!(big("-5"), Value::Int(-5)));
+
+        let huge = big("340282366920938463463374607431768211456");
+        assert!(to_int(&huge).is_err());
+        assert_eq!(to_big_int(&huge).unwrap().to_string(), huge.to_string());
+        assert_eq!(to_int_as::<u8>(&Value::Int(255)).unwrap(), 255);
+        assert!(to_int_as::<u8>(&Value::Int(256)).is_err());
+        assert!(to_int_as::<usize>(&Value::Int(-1)).is_err());
+    }
+
+    #[test]
+    fn test_deep_values() {
+        // Deep enough to overflow the 2MB stack of a test thread by recursion.
+        const DEPTH: usize = 200_000;
+        let long_list = from_vec((0..DEPTH).map(|i| Value::Int(i as i128)).collect());
+        let deep_car = (0..DEPTH).fold(Value::Nil, |acc, _| {
+            Value::Cons(Box::new(acc), Box::new(Value::Int(1)))
+        });
+        for val in [long_list, deep_car].iter() {
+            let bits = modulate_to_string(val);
+            let back = try_demodulate_from_string(&bits).unwrap();
+            assert_eq!(modulate_to_string(&back.clone()), bits);
+            assert_eq!(back.to_string(), val.to_string());
+            assert_eq!(format!("{:?}", back), format!("{:?}", val));
+        }
+
+        let list = from_vec(vec![Value::Int(1), Value::Int(2)]);
+        assert_eq!(list.to_string(), "(1 2)");
+        assert_eq!(format!("{:?}", list), "Cons(Int(1), Cons(Int(2), Nil))");
+        let pair = Value::Cons(Box::new(list), Box::new(Value::Int(-3)));
+        assert_eq!(pair.to_string(), "((1 2) . -3)");
+        assert!(to_vec(pair.clone()).is_err());
+        assert_eq!(
+            to_vec(from_vec(vec![pair.clone(), Value::Nil]))
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json() {
+        let big = "-1234567890123456789012345678901234567890123456789";
+        let json = format!(r#"[1,[],{{"car":2,"cdr":-3}},[[4]],"{}"]"#, big);
+        let val: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(val.to_string(), format!("(1 nil (2 . -3) ((4)) {})", big));
+        assert_eq!(serde_json::to_string(&val).unwrap(), json);
+    }
+
+    #[test]
+    fn test_from_str() {
+        let parse = |s: &str| s.parse::<Value>().map(|v| v.to_string());
+        for (input, expected) in [
+            ("-123", "-123"),
+            ("nil", "nil"),
+            ("()", "nil"),
+            ("(1 . 2)", "(1 . 2)"),
+            ("(1 2 . 3)", "(1 . (2 . 3))"),
+            ("(1 2 . nil)", "(1 2)"),
+            ("(1 (2 3) ())", "(1 (2 3) nil)"),
+            ("( 1 , 2 )", "(1 2)"),
+            ("[1, [2, 3], []]", "(1 (2 3) nil)"),
+            ("[(1 . 2)]", "((1 . 2))"),
+            ("ap ap cons 1 nil", "(1)"),
+            ("ap ap cons 1 ap ap cons 2 3", "(1 . (2 . 3))"),
+            ("ap ap vec 1 2", "(1 . 2)"),
+            ("[ap ap cons 1 2, 3]", "((1 . 2) 3)"),
+            ("ap ap cons (1 2) [3]", "((1 2) 3)"),
+            (
+                "12345678901234567890123456789012345678901234567890",
+                "12345678901234567890123456789012345678901234567890",
+            ),
+        ]
+        .iter()
+        {
+            assert_eq!(parse(input).unwrap(), *expected, "{}", input);
+        }
+        for input in [
+            "",
+            "(1 2",
+            "1 2",
+            "(1 . 2 3)",
+            "(. 1)",
+            "(1 .)",
+            "[1)",
+            "ap cons 1",
+            "ap 1 2",
+            "(ap cons 1)",
+            "foo",
+            "(1,, 2 . , 3)",
+        ]
+        .iter()
+        {
+            assert!(parse(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_to_ap() {
+        let val: Value = "(1 (2 . -3) nil)".parse().unwrap();
+        assert_eq!(
+            val.to_ap(),
+            "ap ap cons 1 ap ap cons ap ap cons 2 -3 ap ap cons nil nil"
+        );
+        assert_eq!(
+            val.to_ap().parse::<Value>().unwrap().to_string(),
+            val.to_string()
+        );
+
+        // Deep inputs do not overflow the stack.
+        let deep = "ap ap cons 1 ".repeat(100_000) + "nil";
+        let val: Value = deep.parse().unwrap();
+        assert_eq!(val.to_ap(), deep);
+        assert_eq!(to_vec(val).unwrap().len(), 100_000);
+    }
+
+    #[test]
+    fn test_try_demod() {
+        assert_eq!(
+            try_demodulate_from_string("01100001").unwrap().to_string(),
+            "1"
+        );
+        assert_eq!(
+            try_demodulate_from_string("0110 0001").unwrap_err(),
+            DemodulateError::InvalidChar(4)
+        );
+        assert_eq!(
+            try_demodulate_from_string("1101100001").unwrap_err(),
+            DemodulateError::UnexpectedEnd(10)
+        );
+        assert_eq!(
+            try_demodulate_from_string("01000").unwrap_err(),
+            DemodulateError::TrailingBits(3)
+        );
+    }
+
+    #[test]
+    fn test_demodulator() {
+        let bits = |s: &str| modulate_to_string(&s.parse::<Value>().unwrap());
+        // Values can follow each other directly or on separate lines.
+        let stream = format!("{}{}\n{}\n", bits("1"), bits("(1 (2 3) 4)"), bits("nil"));
+        let vals = Demodulator::new(io::Cursor::new(stream))
+            .map(|v| v.unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(vals, vec!["1", "(1 (2 3) 4)", "nil"]);
+
+        // Cut in the middle of the 3.
+        let list = bits("(1 (2 3) 4)");
+        let cut = &list[..list.find(&bits("3")).unwrap() + 5];
+        let mut d = Demodulator::from_bytes(cut.as_bytes());
+        let err = d.next().unwrap().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("unexpected end at bit {} in (1 (2 _ ...) ...)", cut.len())
+        );
+        assert!(d.next().is_none());
+
+        let err = Demodulator::from_bytes(b"11 01100001")
+            .read_value()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid character at bit 2 in (_ ...)".to_string()
+        );
+        let stream = format!("11{}0", bits("1"));
+        match Demodulator::from_bytes(stream.as_bytes()).read_value() {
+            Err(StreamError::Invalid { error, partial }) => {
+                assert_eq!(error, DemodulateError::UnexpectedEnd(11));
+                assert_eq!(partial, "(1 . _)");
+            }
+            res => panic!(
+                "unexpected result: {:?}",
+                res.map(|v| v.map(|v| v.to_string()))
+            ),
+        }
+        assert!(Demodulator::from_bytes(b" \n")
+            .read_value()
+            .unwrap()
+            .is_none());
+    }
 }
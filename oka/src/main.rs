@@ -1,4 +1,3 @@
-#![allow(non_snake_case, unused, non_upper_case_globals)]
 // Copyright 2020 Google LLC
 // Copyright 2020 Team Spacecat
 //
@@ -14,325 +13,440 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-
-extern crate clap;
-#[macro_use]
-extern crate itertools;
-
-use std::fs::File;
-use std::io::Read;
-use std::{
-    io::{BufReader, BufWriter},
-    str::FromStr,
-};
-
-type Writer = Box<dyn std::io::Write>;
-type Reader = Box<dyn std::io::Read>;
-type Error = Box<dyn std::error::Error>;
-
-fn main() {
-    let child = std::thread::Builder::new()
-        .stack_size(256 * 1024 * 1024)
-        .spawn(move || run().unwrap())
-        .unwrap();
-    child.join().unwrap();
-}
-
-fn writer(file: Option<&str>) -> Result<Writer, Error> {
-    Ok(if let Some(f) = file {
-        Box::new(std::fs::File::create(f)?)
-    } else {
-        Box::new(std::io::stdout())
-    })
-}
-
-fn reader(file: Option<&str>) -> Result<Reader, Error> {
-    Ok(if let Some(f) = file {
-        Box::new(std::fs::File::open(f)?)
-    } else {
-        Box::new(std::io::stdin())
-    })
-}
-
-fn run() -> Result<(), Error> {
-    let m = clap::App::new("modulate")
+// Converts values between the modulated bits and the text notations:
+//
+//   modulator modulate <<< '(1 81740)'
+//   modulator demodulate --ap <<< 110110000111011111100001001111110100110000
+//   modulator convert --from json --to sexp --batch -i values.txt
+//   modulator roundtrip --batch -i responses.txt
+//   modulator annotate <<< 1101100001110110001000
+
+use anyhow::{anyhow, bail, Result};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use num_bigint::BigUint;
+use rust_game_base::pretty::first_diff;
+use rust_game_base::value::*;
+use std::fmt::{self, Write as _};
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+fn main() -> Result<()> {
+    let format_arg = |name, default| {
+        Arg::with_name(name)
+            .long(name)
+            .short(&name[..1])
+            .takes_value(true)
+            .possible_values(FORMATS)
+            .default_value(default)
+    };
+    let batch = Arg::with_name("batch")
+        .short("b")
+        .long("batch")
+        .help("converts every line separately");
+    let m = App::new("modulator")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
         .arg(
-            clap::Arg::with_name("input")
+            Arg::with_name("input")
                 .short("i")
                 .long("input")
                 .help("input file name")
-                .takes_value(true),
+                .takes_value(true)
+                .global(true),
         )
         .arg(
-            clap::Arg::with_name("output")
+            Arg::with_name("output")
                 .short("o")
                 .long("output")
                 .help("output file name")
-                .takes_value(true),
+                .takes_value(true)
+                .global(true),
         )
-        .arg(
-            clap::Arg::with_name("demodulate")
-                .short("d")
-                .long("demodulate")
-                .help("demodulate given list"),
+        .subcommand(
+            SubCommand::with_name("convert")
+                .about("converts between notations")
+                .arg(format_arg("from", "sexp"))
+                .arg(format_arg("to", "bits"))
+                .arg(batch.clone()),
         )
-        .arg(
-            clap::Arg::with_name("decimal")
-                .short("d")
-                .long("decimal")
-                .help("use decimal notation in demodulation"),
+        .subcommand(
+            SubCommand::with_name("modulate")
+                .about("modulates a list or ap notation")
+                .arg(batch.clone()),
         )
-        .arg(
-            clap::Arg::with_name("ap")
-            .long("ap")
-            .help("demodulate as ap format"),
+        .subcommand(
+            SubCommand::with_name("demodulate")
+                .about("demodulates bits")
+                .arg(
+                    Arg::with_name("decimal")
+                        .long("decimal")
+                        .help("reads the bits as a decimal number"),
+                )
+                .arg(
+                    Arg::with_name("ap")
+                        .long("ap")
+                        .help("prints in ap notation"),
+                )
+                .arg(batch.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("roundtrip")
+                .about("checks that the input converts to every notation and back")
+                .arg(format_arg("from", "bits"))
+                .arg(batch.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("annotate")
+                .about("prints the bits of every node of the value")
+                .arg(format_arg("from", "bits"))
+                .arg(batch),
         )
         .get_matches();
 
-    let mut w = writer(m.value_of("output"))?;
-    let mut r = reader(m.value_of("input"))?;
-
-    let demod = m.is_present("demodulate");
-
-    let mut s = String::new();
-    r.read_to_string(&mut s);
+    let mut input = String::new();
+    match m.value_of("input") {
+        Some(path) => input = std::fs::read_to_string(path)?,
+        None => {
+            std::io::stdin().read_to_string(&mut input)?;
+        }
+    }
+    let mut output: Box<dyn Write> = match m.value_of("output") {
+        Some(path) => Box::new(std::fs::File::create(path)?),
+        None => Box::new(std::io::stdout()),
+    };
+
+    let (name, sub) = m.subcommand();
+    let sub = sub.expect("a subcommand is required");
+    let format = |name| sub.value_of(name).unwrap().parse::<Format>().unwrap();
+    let run = |line: &str| -> Result<String> {
+        match name {
+            "convert" => render(&parse(line, format("from"))?, format("to")),
+            "modulate" => render(&parse(line, Format::Sexp)?, Format::Bits),
+            "demodulate" => {
+                let from = if sub.is_present("decimal") {
+                    Format::Decimal
+                } else {
+                    Format::Bits
+                };
+                let to = if sub.is_present("ap") {
+                    Format::Ap
+                } else {
+                    Format::Sexp
+                };
+                render(&parse(line, from)?, to)
+            }
+            "roundtrip" => roundtrip(line, format("from")).map(|()| "ok".to_string()),
+            "annotate" => Ok(annotate(&parse(line, format("from"))?)),
+            _ => unreachable!("clap rejects other subcommands"),
+        }
+    };
+    each_input(&input, sub, run, &mut output)
+}
 
-    if demod {
-        if m.is_present("decimal") {
-            let i = i64::from_str(&s)?;
-            s = format!("{:b}", i);
-            dbg!(&s);
+// Runs `run` on the whole input, or on every non-empty line with --batch.
+// In batch mode a failed line is reported and the rest still run.
+fn each_input(
+    input: &str,
+    m: &ArgMatches,
+    mut run: impl FnMut(&str) -> Result<String>,
+    output: &mut dyn Write,
+) -> Result<()> {
+    if !m.is_present("batch") {
+        writeln!(output, "{}", run(input.trim())?)?;
+        return Ok(());
+    }
+    let mut failed = 0;
+    let mut total = 0;
+    for (i, line) in input.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
         }
-        let val = demodulate(&mut s.trim().chars().map(|c| c == '1'))
-            .ok_or(Error::from("demodulate failed"))?;
-        if m.is_present("ap") {
-            println!("{}", val.to_ap());
-        } else {
-            println!("{}", val.to_string());
+        total += 1;
+        match run(line.trim()) {
+            Ok(out) => writeln!(output, "{}", out)?,
+            Err(e) => {
+                failed += 1;
+                eprintln!("line {}: {}", i + 1, e);
+            }
         }
-    } else {
-        let val = Value::from_str(&s)?;
-        let v = modulate_to_string(&val);
-        println!("{}", v);
     }
-
+    if failed > 0 {
+        bail!("{} of {} lines failed", failed, total);
+    }
     Ok(())
 }
 
-#[derive(Debug)]
-enum Value {
-    Int(i64),
-    Nil,
-    Cons(Box<Value>, Box<Value>),
-}
-
-impl ToString for Value {
-    fn to_string(&self) -> String {
-        match self {
-            &Value::Int(n) => format!("{}", n),
-            Value::Nil => format!("nil"),
-            Value::Cons(x, y) => format!("({}, {})", x.to_string(), y.to_string()),
-        }
-    }
+const FORMATS: &[&str] = &["sexp", "ap", "json", "bits", "decimal"];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Format {
+    // (1 2 . 3), also accepting [1, 2] and ap notation as input.
+    Sexp,
+    // ap ap cons 1 nil, as in galaxy.txt.
+    Ap,
+    // As in rust_game_base::value, e.g. [1, {"car": 2, "cdr": 3}].
+    Json,
+    Bits,
+    // The bits as a binary number, written in decimal. Leading zeros are
+    // lost, so only values whose bits start with 1 come back.
+    Decimal,
 }
 
-impl FromStr for Value {
-    type Err = Error;
-    fn from_str(mut s: &str) -> Result<Self, Self::Err> {
-        Value::from_iter(&mut s.bytes().peekable())
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Format> {
+        Ok(match s {
+            "sexp" => Format::Sexp,
+            "ap" => Format::Ap,
+            "json" => Format::Json,
+            "bits" => Format::Bits,
+            "decimal" => Format::Decimal,
+            _ => bail!("unknown format: {}", s),
+        })
     }
 }
 
-impl Value {
-    fn from_iter<I: Iterator<Item = u8>>(i: &mut std::iter::Peekable<I>) -> Result<Self, Error> {
-        let b: u8 = i.next().ok_or(Error::from("iterator exhausted"))?;
-        match b {
-            b'(' => {
-                let x = Value::from_iter(i)?;
-                i.find(|c| *c == b',').ok_or(Error::from(", not found"))?;
-                let y = Value::from_iter(i)?;
-                i.find(|c| *c == b')').ok_or(Error::from(") not found"))?;
-                Ok(Value::Cons(Box::new(x), Box::new(y)))
-            }
-            b'-' | b'0'..=b'9' => {
-                let neg = b == b'-';
-                let mut x: i64 = if neg { 0 } else { (b - b'0') as i64 };
-                loop {
-                    if let Some(c) = i.peek() {
-                        if b'0' <= *c && *c <= b'9' {
-                            x = x * 10 + (*c - b'0') as i64;
-                            i.next().unwrap();
-                        } else {
-                            break;
-                        }
-                    } else {
-                        break;
-                    }
-                }
-                if neg {
-                    x = -x;
-                }
-                Ok(Value::Int(x))
+// The old notation wrote cons cells as (a, b), which now reads as a list.
+// Refuses a comma in parentheses instead of changing what the input means.
+fn check_no_comma_pairs(s: &str) -> Result<()> {
+    let mut open = vec![];
+    for c in s.chars() {
+        match c {
+            '(' | '[' => open.push(c),
+            ')' | ']' => {
+                open.pop();
             }
-            b'n' => {
-                i.next();
-                i.next();
-                Ok(Value::Nil)
-            }
-            b' ' => Value::from_iter(i),
-            _ => Err(Error::from(format!("parse fail {}", b as char))),
+            ',' if open.last() == Some(&'(') => bail!(
+                "{}: (a, b) is no longer a cons cell; write (a . b), or (a b) for a list",
+                s
+            ),
+            _ => {}
         }
     }
+    Ok(())
+}
 
-    fn to_ap(&self) -> String {
-        match self {
-            &Value::Int(n) => format!("{}", n),
-            Value::Nil => format!("nil"),
-            Value::Cons(x, y) => format!("ap ap cons {} {}", x.to_ap(), y.to_ap()),
+fn parse(s: &str, format: Format) -> Result<Value> {
+    Ok(match format {
+        Format::Sexp => {
+            check_no_comma_pairs(s)?;
+            s.parse()?
         }
-    }
+        Format::Ap => s.parse()?,
+        Format::Json => serde_json::from_str(s)?,
+        Format::Bits => try_demodulate_from_string(s)?,
+        Format::Decimal => {
+            let n = BigUint::from_str(s).map_err(|e| anyhow!("{}: {}", e, s))?;
+            try_demodulate_from_string(&n.to_str_radix(2))?
+        }
+    })
 }
 
-fn vec_to_str(v: &Vec<bool>) -> String {
-    v.iter().map(|x| if *x { "1" } else { "0" }).collect()
+fn render(val: &Value, format: Format) -> Result<String> {
+    Ok(match format {
+        Format::Sexp => val.to_string(),
+        Format::Ap => val.to_ap(),
+        Format::Json => serde_json::to_string(val)?,
+        Format::Bits => modulate_to_string(val),
+        Format::Decimal => {
+            let bits = modulate_to_string(val);
+            BigUint::parse_bytes(bits.as_bytes(), 2)
+                .expect("bits are binary")
+                .to_string()
+        }
+    })
 }
 
-fn vec_from_str(s: &str) -> Vec<bool> {
-    s.chars().map(|x| x == '1').collect()
+// Checks that the value survives every notation, and that bits in the input
+// are the ones modulate produces.
+fn roundtrip(s: &str, from: Format) -> Result<()> {
+    let val = parse(s, from)?;
+    if from == Format::Bits {
+        let bits = modulate_to_string(&val);
+        if bits != s {
+            bail!("modulates to {} instead", bits);
+        }
+    }
+    for &to in &[Format::Sexp, Format::Ap, Format::Json, Format::Bits] {
+        let back = parse(&render(&val, to)?, to)?;
+        if let Some(d) = first_diff(&val, &back) {
+            bail!("{:?}: {}", to, d);
+        }
+    }
+    Ok(())
 }
 
-fn modulate_to_string(val: &Value) -> String {
-    let mut v = vec![];
-    modulate(&val, &mut v);
-    vec_to_str(&v)
+// One node in the bits of a value.
+struct Node<'a> {
+    start: usize,
+    // The end of the node's own bits: "11" for a cell, everything for others.
+    own_end: usize,
+    // The end of the whole subtree.
+    end: usize,
+    depth: usize,
+    val: &'a Value,
 }
 
-fn modulate(val: &Value, v: &mut Vec<bool>) {
-    match val {
-        &Value::Int(n) => {
-            let n = if n >= 0 {
-                v.push(false);
-                v.push(true);
-                n as u64
-            } else {
-                v.push(true);
-                v.push(false);
-                n.abs() as u64
-            };
-
-            let keta = 64 - n.leading_zeros();
-            let t = (keta + 3) / 4;
-
-            for _ in 0..t {
-                v.push(true);
+// The nodes in the order of their bits, which is pre-order.
+fn nodes(val: &Value) -> Vec<Node<'_>> {
+    let mut nodes: Vec<Node> = vec![];
+    // The index of the parent cell of a cdr, whose subtree ends with it.
+    let mut cdr_of = vec![];
+    let mut todo = vec![(val, 0, None)];
+    let mut offset = 0;
+    while let Some((val, depth, parent)) = todo.pop() {
+        let len = match val {
+            Value::Cons(car, cdr) => {
+                todo.push((cdr, depth + 1, Some(nodes.len())));
+                todo.push((car, depth + 1, None));
+                2
             }
-            v.push(false);
-
-            for i in (0..4 * t).rev() {
-                v.push((n >> i) & 1 == 1);
-            }
-        }
-        Value::Nil => {
-            v.push(false);
-            v.push(false);
-        }
-        Value::Cons(hd, tl) => {
-            v.push(true);
-            v.push(true);
-            modulate(hd, v);
-            modulate(tl, v);
+            _ => modulate_to_string(val).len(),
+        };
+        nodes.push(Node {
+            start: offset,
+            own_end: offset + len,
+            end: offset + len,
+            depth,
+            val,
+        });
+        cdr_of.push(parent);
+        offset += len;
+    }
+    // Children come after their parents, so this sees every cdr finished.
+    for i in (0..nodes.len()).rev() {
+        if let Some(parent) = cdr_of[i] {
+            nodes[parent].end = nodes[i].end;
         }
     }
+    nodes
 }
 
-fn demodulate(it: &mut impl Iterator<Item = bool>) -> Option<Value> {
-    let t0 = it.next()?;
-    let t1 = it.next()?;
-
-    Some(match (t0, t1) {
-        (false, false) => Value::Nil,
-        (true, true) => {
-            let x = demodulate(it)?;
-            let y = demodulate(it)?;
-            Value::Cons(Box::new(x), Box::new(y))
-        }
-        (_, y) => {
-            let mut t = 0;
-            while it.next()? {
-                t += 1;
-            }
-            let mut v = 0;
-            for i in (0..4 * t).rev() {
-                v |= (if it.next()? { 1 } else { 0 }) << i;
+// At most `limit` bytes of the value, then "...".
+fn summary(val: &Value, limit: usize) -> String {
+    struct Limited(String, usize);
+    impl fmt::Write for Limited {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            if self.0.len() + s.len() > self.1 {
+                return Err(fmt::Error);
             }
-            Value::Int(if y { v } else { -v })
+            self.0.push_str(s);
+            Ok(())
         }
-    })
+    }
+    let mut out = Limited(String::new(), limit);
+    if write!(out, "{}", val).is_err() {
+        out.0.push_str("...");
+    }
+    out.0
+}
+
+// Prints a line per node with its range of bits, its own bits and the value,
+// indented by depth:
+//
+//          0..22  11  (1 2)
+//          2..10    01100001  1
+//         10..22    11  (2)
+fn annotate(val: &Value) -> String {
+    let bits = modulate_to_string(val);
+    let mut out = String::new();
+    for node in nodes(val) {
+        let range = format!("{}..{}", node.start, node.end);
+        let indent = " ".repeat(node.depth * 2);
+        let own = &bits[node.start..node.own_end];
+        writeln!(
+            out,
+            "{:>12}  {}{}  {}",
+            range,
+            indent,
+            own,
+            summary(node.val, 60)
+        )
+        .unwrap();
+    }
+    out.pop();
+    out
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    #[test]
-    fn test_demod() {
-        for tc in [
-            (
-                "110110000111011111100001001111110100110000",
-                "(1, (81740, nil))",
-            ),
-            ("010", "0"),
-            ("01100001", "1"),
-            ("10100001", "-1"),
-        ]
-        .iter()
-        {
-            let bin = tc.0;
-            let lst = tc.1;
 
-            assert_eq!(
-                lst,
-                demodulate(&mut vec_from_str(bin).into_iter())
-                    .unwrap()
-                    .to_string()
-                    .as_str()
-            );
-        }
+    #[test]
+    fn test_convert() {
+        let bits = "110110000111011111100001001111110100110000";
+        let val = parse(bits, Format::Bits).unwrap();
+        assert_eq!(render(&val, Format::Sexp).unwrap(), "(1 81740)");
+        assert_eq!(
+            render(&val, Format::Ap).unwrap(),
+            "ap ap cons 1 ap ap cons 81740 nil"
+        );
+        assert_eq!(render(&val, Format::Json).unwrap(), "[1,81740]");
+        let decimal = render(&val, Format::Decimal).unwrap();
+        assert_eq!(
+            render(&parse(&decimal, Format::Decimal).unwrap(), Format::Bits).unwrap(),
+            bits
+        );
+        assert_eq!(
+            parse("[1, 81740]", Format::Json).unwrap().to_string(),
+            "(1 81740)"
+        );
+        assert_eq!(
+            parse("ap ap cons 1 (81740)", Format::Ap)
+                .unwrap()
+                .to_string(),
+            "(1 81740)"
+        );
+        assert!(parse("0110", Format::Bits).is_err());
     }
 
     #[test]
-    fn test_mod() {
-        for tc in [
-            (
-                "110110000111011111100001001111110100110000",
-                "(1, (81740, nil))",
-            ),
-            ("010", "0"),
-            ("01100001", "1"),
-            ("10100001", "-1"),
-        ]
-        .iter()
-        {
-            let bin = tc.0;
-            let lst = tc.1;
-
-            let val = Value::from_str(lst).unwrap();
-            let v = modulate_to_string(&val);
-            assert_eq!(&v, bin);
-        }
+    fn test_roundtrip() {
+        assert!(roundtrip("(1 (2 . -3) nil)", Format::Sexp).is_ok());
+        assert!(roundtrip("[1, [2, 3]]", Format::Sexp).is_ok());
+        assert!(parse("(1, (81740, nil))", Format::Sexp).is_err());
+        assert!(parse("[1, (2, 3)]", Format::Sexp).is_err());
+        assert!(roundtrip("1101100001110110000100", Format::Bits).is_ok());
+        // Zero with an extra nibble demodulates, but is not what modulate
+        // writes.
+        assert_eq!(
+            roundtrip("01100000", Format::Bits).unwrap_err().to_string(),
+            "modulates to 010 instead"
+        );
     }
 
     #[test]
-    fn test_mod_demod() {
-        let lst = "(1, (-81740, nil))";
+    fn test_annotate() {
+        let val = parse("(1 . nil)", Format::Sexp).unwrap();
+        let lines = annotate(&val);
+        let lines = lines.lines().map(|l| l.trim()).collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                "0..12  11  (1)",
+                "2..10    01100001  1",
+                "10..12    00  nil"
+            ]
+        );
 
-        let val = Value::from_str(lst).unwrap();
-        let v = modulate_to_string(&val);
+        let val = parse("((1 2) 3 . 4)", Format::Sexp).unwrap();
+        let ranges = nodes(&val)
+            .iter()
+            .map(|n| (n.start, n.end))
+            .collect::<Vec<_>>();
+        let bits = modulate_to_string(&val);
+        assert_eq!(ranges[0], (0, bits.len()));
+        // The car (1 2) and the cdr (3 . 4) split the rest.
+        let car = &bits[ranges[1].0..ranges[1].1];
+        assert_eq!(demodulate_from_string(car).unwrap().to_string(), "(1 2)");
+        let cdr = nodes(&val)
+            .into_iter()
+            .find(|n| n.depth == 1 && n.start > 2)
+            .unwrap();
         assert_eq!(
-            lst,
-            demodulate(&mut vec_from_str(&v).into_iter())
-                .unwrap()
-                .to_string()
-                .as_str()
+            (cdr.end, summary(cdr.val, 60)),
+            (bits.len(), "(3 . 4)".to_string())
         );
+        assert_eq!(summary(&val, 5), "((1 2...");
     }
 }
@@ -0,0 +1,251 @@
+// Copyright 2020 Google LLC
+// Copyright 2020 Team Spacecat
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::expr::{dsl::*, *};
+use crate::sender::Sender;
+use crate::value::*;
+use crate::Result;
+use num_traits::{ToPrimitive, Zero};
+use std::rc::Rc;
+
+// The points of one image, in the order the protocol draws them.
+pub type Image = Vec<(i64, i64)>;
+
+// What one run of the protocol asks for: with a flag of 0 the data is the
+// images to draw, otherwise it is to be sent to the aliens.
+pub struct Step {
+    pub state: Value,
+    pub images: Vec<Image>,
+    pub outbound: Option<Value>,
+}
+
+pub struct Interaction {
+    pub state: Value,
+    pub images: Vec<Image>,
+    // Everything sent to the aliens on the way, in order.
+    pub outbound: Vec<Value>,
+}
+
+// Runs an interaction protocol like `galaxy` from galaxy.txt:
+//
+//   let engine = GalaxyEngine::from_file("galaxy.txt")?;
+//   let mut sender = CurlSender::new(DEFAULT_URL);
+//   let click = Value::cons(Value::int(0), Value::int(0));
+//   let res = engine.interact(&Value::Nil, &click, &mut sender)?;
+pub struct GalaxyEngine {
+    dict: Dictionary,
+    protocol: String,
+}
+
+impl GalaxyEngine {
+    // Loads definitions in the format of galaxy.txt. The protocol is `galaxy`.
+    pub fn load(program: &str) -> Result<GalaxyEngine> {
+        Ok(GalaxyEngine {
+            dict: parse_functions(program)?,
+            protocol: "galaxy".to_string(),
+        })
+    }
+
+    pub fn from_file(path: &str) -> Result<GalaxyEngine> {
+        GalaxyEngine::load(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn with_protocol(mut self, name: &str) -> GalaxyEngine {
+        self.protocol = name.to_string();
+        self
+    }
+
+    // Applies the protocol to `state` and `event` once. The data of a flag=1
+    // result is returned, not sent, and protocols that call `send` themselves
+    // get an error.
+    pub fn step(&self, state: &Value, event: &Value) -> Result<Step> {
+        self.step_in(&Context::new(&self.dict), state, event)
+    }
+
+    fn step_in(&self, ctx: &Context, state: &Value, event: &Value) -> Result<Step> {
+        let expr = app(
+            app(atom(&self.protocol), value_to_expr(state)),
+            value_to_expr(event),
+        );
+        let res = expr_to_list(eval(expr, ctx)?, ctx)?;
+        if res.len() != 3 {
+            return Err(format!("{}: returned {} values, not 3", self.protocol, res.len()).into());
+        }
+
+        let flag = as_num(Rc::clone(&res[0]), ctx)?
+            .ok_or_else(|| format!("{}: flag is not an integer", self.protocol))?;
+        let state = expr_to_value(Rc::clone(&res[1]), ctx)?;
+        let data = Rc::clone(&res[2]);
+
+        if flag.is_zero() {
+            Ok(Step {
+                state,
+                images: expr_to_images(data, ctx)?,
+                outbound: None,
+            })
+        } else {
+            Ok(Step {
+                state,
+                images: vec![],
+                outbound: Some(expr_to_value(data, ctx)?),
+            })
+        }
+    }
+
+    // Runs the protocol until it has images to draw, sending the data it asks
+    // for through `sender` and feeding back the responses.
+    pub fn interact(
+        &self,
+        state: &Value,
+        event: &Value,
+        sender: &mut dyn Sender,
+    ) -> Result<Interaction> {
+        let ctx = Context::with_sender(&self.dict, sender);
+        let mut outbound = vec![];
+        let mut step = self.step_in(&ctx, state, event)?;
+        while let Some(data) = step.outbound.take() {
+            let response = ctx.send(&data)?;
+            outbound.push(data);
+            step = self.step_in(&ctx, &step.state, &response)?;
+        }
+        Ok(Interaction {
+            state: step.state,
+            images: step.images,
+            outbound,
+        })
+    }
+}
+
+// Assumes [[(int, int)]].
+fn expr_to_images(images: ExprRef, ctx: &Context) -> Result<Vec<Image>> {
+    let mut vs = vec![];
+
+    for image in expr_to_list(images, ctx)? {
+        let mut v = vec![];
+
+        for pt in expr_to_list(image, ctx)? {
+            let x = as_num(app(atom("car"), Rc::clone(&pt)), ctx)?;
+            let y = as_num(app(atom("cdr"), pt), ctx)?;
+            match (x, y) {
+                (Some(x), Some(y)) => match (x.to_i64(), y.to_i64()) {
+                    (Some(x), Some(y)) => v.push((x, y)),
+                    _ => return Err(format!("images contains ({}, {})", x, y).into()),
+                },
+                _ => return Err("images contains non-integer value".into()),
+            }
+        }
+
+        vs.push(v);
+    }
+
+    Ok(vs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sender::RecordedSender;
+
+    // statelessdraw from the docs of the interact message.
+    const STATELESSDRAW: &str = "galaxy = ap ap c ap ap b b ap ap b ap b ap cons 0 ap ap c ap ap b b cons ap ap c cons nil ap ap c ap ap b cons ap ap c cons nil nil";
+
+    #[test]
+    fn test_step() {
+        let engine = GalaxyEngine::load(STATELESSDRAW).unwrap();
+        let step = engine
+            .step(&Value::Nil, &Value::cons(Value::int(1), Value::int(-2)))
+            .unwrap();
+        assert_eq!(step.state, Value::Nil);
+        assert_eq!(step.images, vec![vec![(1, -2)]]);
+        assert!(step.outbound.is_none());
+
+        let res = engine
+            .interact(
+                &Value::Nil,
+                &Value::cons(Value::int(3), Value::int(4)),
+                &mut RecordedSender::default(),
+            )
+            .unwrap();
+        assert_eq!(res.images, vec![vec![(3, 4)]]);
+        assert!(res.outbound.is_empty());
+    }
+
+    // Sends the cdr of events like (1 . data), and draws the cdr of (0 . images).
+    const RELAY: &str =
+        "relay = ap t ap ap s ap ap b cons car ap ap s cons ap ap b ap ap c cons nil cdr";
+
+    #[test]
+    fn test_interact_sender() {
+        let engine = GalaxyEngine::load(RELAY).unwrap().with_protocol("relay");
+        let event = Value::cons(Value::int(1), Value::int(42));
+        let step = engine.step(&Value::Nil, &event).unwrap();
+        assert_eq!(step.outbound, Some(Value::int(42)));
+        assert!(step.images.is_empty());
+
+        let images = parse_sexp_str("(0 ((1 . 2)))").unwrap();
+        let mut sender = RecordedSender::new(vec![images.clone()]);
+        let res = engine.interact(&Value::Nil, &event, &mut sender).unwrap();
+        assert_eq!(res.state, images);
+        assert_eq!(res.images, vec![vec![(1, 2)]]);
+        assert_eq!(res.outbound, vec![Value::int(42)]);
+        assert_eq!(sender.sent, vec![Value::int(42)]);
+
+        // Counts up while every response asks for another send.
+        let mut sent = vec![];
+        let mut sender = |data: &Value| -> Result<Value> {
+            sent.push(data.clone());
+            match data {
+                Value::Int(n) if *n < 3.into() => Ok(Value::cons(Value::int(1), Value::int(n + 1))),
+                _ => Err("done".into()),
+            }
+        };
+        let start = Value::cons(Value::int(1), Value::int(0));
+        let err = engine.interact(&Value::Nil, &start, &mut sender);
+        assert_eq!(err.err().unwrap().to_string(), "done");
+        assert_eq!(sent.len(), 4);
+    }
+
+    // Calls `send` itself instead of returning a flag of 1.
+    const SEND_ZERO: &str = "sendzero = ap t ap t ap send 0";
+
+    #[test]
+    fn test_send_error() {
+        let engine = GalaxyEngine::load(SEND_ZERO)
+            .unwrap()
+            .with_protocol("sendzero");
+        let err = engine.step(&Value::Nil, &Value::Nil).err().unwrap();
+        assert_eq!(err.to_string(), "send: no sender");
+
+        let mut sender = |_: &Value| -> Result<Value> { Err("connection refused".into()) };
+        let err = engine.interact(&Value::Nil, &Value::Nil, &mut sender);
+        assert_eq!(err.err().unwrap().to_string(), "connection refused");
+    }
+
+    #[test]
+    fn test_galaxy() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/galaxy.txt");
+        let engine = GalaxyEngine::from_file(path).unwrap();
+        let res = engine
+            .interact(
+                &Value::Nil,
+                &Value::cons(Value::int(0), Value::int(0)),
+                &mut RecordedSender::default(),
+            )
+            .unwrap();
+        assert!(res.outbound.is_empty());
+        assert!(!res.images.is_empty());
+        assert_ne!(res.state, Value::Nil);
+    }
+}
@@ -0,0 +1,828 @@
+// Copyright 2020 Google LLC
+// Copyright 2020 Team Spacecat
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::sender::Sender;
+use crate::value::*;
+use crate::Result;
+use num_bigint::BigInt;
+use num_traits::{ToPrimitive, Zero};
+use std::collections::{BTreeMap, VecDeque};
+use std::{cell::RefCell, rc::Rc};
+
+pub type Dictionary = BTreeMap<String, ExprRef>;
+pub type ExprRef = Rc<RefCell<Expr>>;
+type ExprValueRef = Rc<RefCell<ExprValue>>;
+
+#[derive(Debug)]
+pub enum ExprValue {
+    Lam(String, ExprRef),
+    App(ExprRef, ExprRef),
+    Atom(String),
+    Int(BigInt),
+    // The result of `mod`, e.g. "110110000101100010".
+    Bits(String),
+}
+
+impl ExprValue {
+    pub fn atom(&self) -> Option<&str> {
+        match self {
+            ExprValue::Atom(s) => Some(&s),
+            _ => None,
+        }
+    }
+
+    pub fn int(&self) -> Option<&BigInt> {
+        match self {
+            ExprValue::Int(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn bits(&self) -> Option<&str> {
+        match self {
+            ExprValue::Bits(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Expr {
+    value: ExprValueRef,
+    evaluated: Option<ExprRef>,
+}
+
+impl Expr {
+    pub fn new(v: ExprValue) -> ExprRef {
+        Rc::new(RefCell::new(Expr {
+            value: Rc::new(RefCell::new(v)),
+            evaluated: None,
+        }))
+    }
+
+    pub fn make_atom(s: &str) -> ExprRef {
+        Expr::new(ExprValue::Atom(s.to_string()))
+    }
+
+    pub fn make_int(n: BigInt) -> ExprRef {
+        let ret = Expr::new(ExprValue::Int(n));
+        ret.borrow_mut().evaluated = Some(Rc::clone(&ret));
+        ret
+    }
+
+    pub fn make_app(f: ExprRef, x: ExprRef) -> ExprRef {
+        Expr::new(ExprValue::App(f, x))
+    }
+
+    pub fn make_bits(s: String) -> ExprRef {
+        let ret = Expr::new(ExprValue::Bits(s));
+        ret.borrow_mut().evaluated = Some(Rc::clone(&ret));
+        ret
+    }
+}
+
+// What evaluation needs besides the expression: the definitions, and where
+// `send` goes.
+pub struct Context<'a> {
+    pub dict: &'a Dictionary,
+    sender: Option<RefCell<&'a mut dyn Sender>>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(dict: &'a Dictionary) -> Context<'a> {
+        Context { dict, sender: None }
+    }
+
+    pub fn with_sender(dict: &'a Dictionary, sender: &'a mut dyn Sender) -> Context<'a> {
+        Context {
+            dict,
+            sender: Some(RefCell::new(sender)),
+        }
+    }
+
+    pub fn send(&self, data: &Value) -> Result<Value> {
+        match &self.sender {
+            Some(sender) => sender.borrow_mut().send(data),
+            None => Err("send: no sender".into()),
+        }
+    }
+}
+
+pub mod dsl {
+    use super::{BigInt, Expr, ExprRef};
+
+    pub fn app(f: ExprRef, x: ExprRef) -> ExprRef {
+        Expr::make_app(f, x)
+    }
+
+    pub fn atom(name: &str) -> ExprRef {
+        Expr::make_atom(name)
+    }
+
+    pub fn tt() -> ExprRef {
+        Expr::make_atom("t")
+    }
+
+    pub fn ff() -> ExprRef {
+        Expr::make_atom("f")
+    }
+
+    pub fn bool(b: bool) -> ExprRef {
+        if b {
+            tt()
+        } else {
+            ff()
+        }
+    }
+
+    pub fn int<T: Into<BigInt>>(n: T) -> ExprRef {
+        Expr::make_int(n.into())
+    }
+
+    pub fn list(xs: Vec<ExprRef>) -> ExprRef {
+        let mut ret = atom("nil");
+        for x in xs.into_iter().rev() {
+            ret = app(app(atom("cons"), x), ret);
+        }
+        ret
+    }
+}
+
+pub fn as_num(x: ExprRef, ctx: &Context) -> Result<Option<BigInt>> {
+    let x = eval(x, ctx)?;
+    let n = x.borrow().value.borrow().int().cloned();
+    Ok(n)
+}
+
+// Evaluates e to weak head normal form. The subexpressions a reduction needs
+// first are evaluated on an explicit stack instead of by recursion, so long
+// sessions don't overflow the native stack.
+pub fn eval(e: ExprRef, ctx: &Context) -> Result<ExprRef> {
+    if let Some(e) = &e.borrow().evaluated {
+        return Ok(Rc::clone(&e));
+    }
+
+    // (expression whose result is memoized, where its reduction has got to)
+    let mut stack = vec![(Rc::clone(&e), e)];
+
+    loop {
+        let res = reduce(Rc::clone(&stack.last().unwrap().1), ctx);
+        match res {
+            Err(Stop::Pending(x)) => stack.push((Rc::clone(&x), x)),
+            Err(Stop::Failed(e)) => return Err(e),
+            Ok(res) => {
+                let (init_expr, cur) = stack.last_mut().unwrap();
+                if !Rc::ptr_eq(&res, cur) {
+                    *cur = res;
+                    continue;
+                }
+                init_expr.borrow_mut().evaluated = Some(Rc::clone(&res));
+                stack.pop();
+                if stack.is_empty() {
+                    return Ok(res);
+                }
+            }
+        }
+    }
+}
+
+// Why a reduction stopped without a result.
+enum Stop {
+    // A subexpression that has to be evaluated before the reduction can go on.
+    Pending(ExprRef),
+    // The reduction failed, e.g. because `send` did.
+    Failed(Box<dyn std::error::Error>),
+}
+
+impl From<Box<dyn std::error::Error>> for Stop {
+    fn from(e: Box<dyn std::error::Error>) -> Stop {
+        Stop::Failed(e)
+    }
+}
+
+type Reduced = std::result::Result<ExprRef, Stop>;
+
+fn fail(msg: String) -> Stop {
+    Stop::Failed(msg.into())
+}
+
+fn forced(e: &ExprRef) -> Reduced {
+    match &e.borrow().evaluated {
+        Some(v) => Ok(Rc::clone(v)),
+        None => Err(Stop::Pending(Rc::clone(e))),
+    }
+}
+
+// Reduces e once, evaluating what it needs first.
+pub fn try_eval(e: ExprRef, ctx: &Context) -> Result<ExprRef> {
+    loop {
+        match reduce(Rc::clone(&e), ctx) {
+            Ok(res) => return Ok(res),
+            Err(Stop::Pending(x)) => {
+                eval(x, ctx)?;
+            }
+            Err(Stop::Failed(e)) => return Err(e),
+        }
+    }
+}
+
+// Like try_eval, but gives back the first unevaluated subexpression it needs
+// instead of evaluating it, and is retried once eval has done that.
+fn reduce(e: ExprRef, ctx: &Context) -> Reduced {
+    use dsl::*;
+
+    let eval_cons = |a: ExprRef, b: ExprRef| {
+        let ret = app(app(atom("cons"), forced(&a)?), forced(&b)?);
+        ret.borrow_mut().evaluated = Some(Rc::clone(&ret));
+        Ok(ret)
+    };
+
+    let num = |fname, x: ExprRef| -> std::result::Result<BigInt, Stop> {
+        let x = forced(&x)?;
+        let n = x.borrow().value.borrow().int().cloned();
+        n.ok_or_else(|| fail(format!("{}: argument is not int", fname)))
+    };
+
+    if let Some(e) = &e.borrow().evaluated {
+        return Ok(Rc::clone(e));
+    }
+
+    if let Some(name) = e.borrow().value.borrow().atom() {
+        if let Some(ret) = ctx.dict.get(name) {
+            return Ok(Rc::clone(ret));
+        }
+    }
+
+    if Rc::clone(&e).borrow().value.borrow().int().is_some() {
+        return Ok(e);
+    }
+
+    let bf = e.borrow();
+    let bbf = bf.value.borrow();
+    if let ExprValue::App(f, x) = &*bbf {
+        let f = forced(f)?;
+        let x = Rc::clone(x);
+
+        match f.borrow().value.borrow().atom() {
+            Some("neg") => return Ok(Expr::make_int(-num("neg", x)?)),
+            Some("i") => return Ok(x),
+            Some("nil") => return Ok(tt()),
+            Some("isnil") => return Ok(app(x, app(tt(), app(tt(), ff())))),
+            Some("car") => return Ok(app(x, tt())),
+            Some("cdr") => return Ok(app(x, ff())),
+            Some("inc") => return Ok(int(num("inc", x)? + 1)),
+            Some("dec") => return Ok(int(num("dec", x)? - 1)),
+            Some("pwr2") => {
+                let n = num("pwr2", x)?;
+                let n = n
+                    .to_usize()
+                    .ok_or_else(|| fail(format!("pwr2: bad exponent {}", n)))?;
+                return Ok(int(BigInt::from(1) << n));
+            }
+            Some("mod") => return Ok(Expr::make_bits(encode(&modulate(&expr_to_value(x, ctx)?)))),
+            Some("dem") => {
+                let x = forced(&x)?;
+                let x = x.borrow();
+                let x = x.value.borrow();
+                let bits = x
+                    .bits()
+                    .ok_or_else(|| fail("dem: argument is not bits".to_string()))?;
+                let val = decode(bits)
+                    .and_then(|bits| demodulate(&bits))
+                    .ok_or_else(|| fail(format!("dem: invalid bits {}", bits)))?;
+                return Ok(value_to_expr(&val));
+            }
+            Some("modem") => return Ok(value_to_expr(&expr_to_value(x, ctx)?)),
+            Some("send") => {
+                let resp = ctx.send(&expr_to_value(x, ctx)?)?;
+                return Ok(value_to_expr(&resp));
+            }
+            // Pictures are just their lists of points, evaluated.
+            Some("draw") | Some("multipledraw") => {
+                return Ok(value_to_expr(&expr_to_value(x, ctx)?))
+            }
+            _ => {}
+        }
+
+        let bf = f.borrow();
+        let bbf = bf.value.borrow();
+        if let ExprValue::App(f, y) = &*bbf {
+            let f = forced(f)?;
+            let y = Rc::clone(y);
+
+            match f.borrow().value.borrow().atom() {
+                Some("t") => return Ok(y),
+                Some("f") => return Ok(x),
+                Some("add") => return Ok(int(num("add", y)? + num("add", x)?)),
+                Some("mul") => return Ok(int(num("mul", y)? * num("mul", x)?)),
+                Some("div") => {
+                    let (n, d) = (num("div", y)?, num("div", x)?);
+                    if d.is_zero() {
+                        return Err(fail(format!("div: {} / 0", n)));
+                    }
+                    return Ok(int(n / d));
+                }
+                Some("lt") => return Ok(bool(num("lt", y)? < num("lt", x)?)),
+                Some("eq") => return Ok(bool(num("eq", y)? == num("eq", x)?)),
+                Some("cons") | Some("vec") => return eval_cons(y, x),
+                Some("checkerboard") => return checkerboard(num("checkerboard", y)?),
+                Some("statelessdraw") => {
+                    return Ok(list(vec![int(0), atom("nil"), list(vec![list(vec![x])])]))
+                }
+                Some("statefuldraw") => {
+                    let state = app(app(atom("cons"), x), y);
+                    return Ok(list(vec![int(0), Rc::clone(&state), list(vec![state])]));
+                }
+                _ => {}
+            }
+
+            let bf = f.borrow();
+            let bbf = bf.value.borrow();
+            if let ExprValue::App(f, z) = &*bbf {
+                let f = forced(f)?;
+                let z = Rc::clone(z);
+
+                let bf = f.borrow();
+                let bbf = bf.value.borrow();
+
+                match bbf.atom() {
+                    Some("s") => return Ok(app(app(z, Rc::clone(&x)), app(y, x))),
+                    Some("c") => return Ok(app(app(z, x), y)),
+                    Some("b") => return Ok(app(z, app(y, x))),
+                    Some("cons") | Some("vec") => return Ok(app(app(x, z), y)),
+                    Some("if0") => return Ok(if num("if0", z)?.is_zero() { y } else { x }),
+                    // ap ap ap interact x2 x4 x3 = ap ap f38 x2 ap ap x2 x4 x3, where f38
+                    // draws the data with a flag of 0 and sends it otherwise.
+                    Some("interact") => {
+                        let res = app(app(Rc::clone(&z), y), x);
+                        let nth = |n| {
+                            let mut e = Rc::clone(&res);
+                            for _ in 0..n {
+                                e = app(atom("cdr"), e);
+                            }
+                            app(atom("car"), e)
+                        };
+                        let state = app(atom("modem"), nth(1));
+                        let data = nth(2);
+                        let draw = list(vec![
+                            Rc::clone(&state),
+                            app(atom("multipledraw"), Rc::clone(&data)),
+                        ]);
+                        let again = app(
+                            app(app(atom("interact"), z), state),
+                            app(atom("send"), data),
+                        );
+                        return Ok(app(app(app(atom("if0"), nth(0)), draw), again));
+                    }
+
+                    Some(f) => return Err(fail(format!("invalid function: {}", f))),
+                    _ => {
+                        return Err(fail(format!(
+                            "invalid ap: {:?}, {:?}, {:?}, {:?}",
+                            bbf,
+                            x.borrow().value,
+                            y.borrow().value,
+                            z.borrow().value,
+                        )))
+                    }
+                }
+            }
+        }
+    }
+
+    drop(bbf);
+    drop(bf);
+    Ok(e)
+}
+
+// The points (x, y) in [0, n) x [0, n) with an even x + y.
+fn checkerboard(n: BigInt) -> Reduced {
+    let n = n
+        .to_i64()
+        .ok_or_else(|| fail(format!("checkerboard: size {} is too large", n)))?;
+    let mut points = vec![];
+    for x in 0..n {
+        for y in 0..n {
+            if (x + y) % 2 == 0 {
+                points.push(Value::cons(Value::int(x), Value::int(y)));
+            }
+        }
+    }
+    Ok(value_to_expr(&Value::list(points)))
+}
+
+// What parse is in the middle of. Galaxy lines nest deeper than recursion
+// allows, like the values in expr_to_value.
+enum Parsing {
+    // `ap`, and its function once that is parsed.
+    Ap(Option<ExprRef>),
+    // The elements of a `( a , b , ... )` so far.
+    List(Vec<ExprRef>),
+}
+
+fn parse<'a>(it: &mut impl Iterator<Item = &'a str>) -> Result<ExprRef> {
+    use dsl::*;
+
+    let mut next = || it.next().ok_or("unexpected end of expression");
+    let mut open: Vec<Parsing> = vec![];
+    loop {
+        let s = next()?;
+        let mut ret = match s {
+            "ap" => {
+                open.push(Parsing::Ap(None));
+                continue;
+            }
+            "(" => {
+                open.push(Parsing::List(vec![]));
+                continue;
+            }
+            ")" if matches!(open.last(), Some(Parsing::List(v)) if v.is_empty()) => {
+                open.pop();
+                atom("nil")
+            }
+            _ => match s.parse::<BigInt>() {
+                Ok(n) => int(n),
+                Err(_) => atom(s),
+            },
+        };
+        // Completes what ret finishes, until something needs the next token.
+        loop {
+            match open.pop() {
+                None => return Ok(ret),
+                Some(Parsing::Ap(None)) => {
+                    open.push(Parsing::Ap(Some(ret)));
+                    break;
+                }
+                Some(Parsing::Ap(Some(f))) => ret = Expr::make_app(f, ret),
+                Some(Parsing::List(mut v)) => {
+                    v.push(ret);
+                    match next()? {
+                        "," => {
+                            open.push(Parsing::List(v));
+                            break;
+                        }
+                        ")" => ret = list(v),
+                        s => return Err(format!("expected , or ) but found {}", s).into()),
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn parse_expr(s: &str) -> Result<ExprRef> {
+    let mut it = s.split_whitespace();
+    let ret = parse(&mut it)?;
+    match it.next() {
+        None => Ok(ret),
+        Some(s) => Err(format!("unexpected {} after the expression", s).into()),
+    }
+}
+
+// Reads definitions like `:1029 = ap ap cons 7 ap ap cons 123229502148636 nil`,
+// one per line, as in galaxy.txt.
+pub fn parse_functions(program: &str) -> Result<Dictionary> {
+    let mut dict = Dictionary::new();
+
+    for (i, s) in program.lines().enumerate() {
+        if s.chars().all(|c| c.is_whitespace()) {
+            continue;
+        }
+        if s.starts_with("//") {
+            continue;
+        }
+
+        let mut jt = s.split('=');
+        let (lhs, rhs) = match (jt.next(), jt.next()) {
+            (Some(lhs), Some(rhs)) => {
+                let parse = |s| parse_expr(s).map_err(|e| format!("line {}: {}", i + 1, e));
+                (parse(lhs)?, parse(rhs)?)
+            }
+            _ => return Err(format!("line {}: not a definition: {}", i + 1, s).into()),
+        };
+
+        let b = lhs.borrow();
+        let b = b.value.borrow();
+        if let Some(name) = b.atom() {
+            dict.insert(name.to_string(), rhs);
+        }
+    }
+    Ok(dict)
+}
+
+pub fn expr_to_bool(e: ExprRef, ctx: &Context) -> Result<bool> {
+    use dsl::*;
+    let n = as_num(app(app(e, int(0)), int(1)), ctx)?;
+    Ok(n.ok_or("expr_to_bool: failed to test bool")?.is_zero())
+}
+
+pub fn expr_to_list(e: ExprRef, ctx: &Context) -> Result<VecDeque<ExprRef>> {
+    use dsl::*;
+
+    let mut xs = VecDeque::new();
+    let mut e = e;
+    while !expr_to_bool(app(atom("isnil"), Rc::clone(&e)), ctx)? {
+        xs.push_back(app(atom("car"), Rc::clone(&e)));
+        e = app(atom("cdr"), e);
+    }
+    Ok(xs)
+}
+
+// Values are built bottom-up on explicit stacks, as states and pictures can be
+// nested deeper than the native stack allows.
+enum Visit<T> {
+    Node(T),
+    // Pops the cdr and then the car of a cons.
+    Cons,
+}
+
+pub fn expr_to_value(e: ExprRef, ctx: &Context) -> Result<Value> {
+    use dsl::*;
+
+    let mut todo = vec![Visit::Node(e)];
+    let mut done = vec![];
+
+    while let Some(visit) = todo.pop() {
+        match visit {
+            Visit::Node(e) => {
+                if let Some(n) = as_num(Rc::clone(&e), ctx)? {
+                    done.push(Value::Int(n));
+                } else if expr_to_bool(app(atom("isnil"), Rc::clone(&e)), ctx)? {
+                    done.push(Value::Nil);
+                } else {
+                    todo.push(Visit::Cons);
+                    todo.push(Visit::Node(app(atom("cdr"), Rc::clone(&e))));
+                    todo.push(Visit::Node(app(atom("car"), e)));
+                }
+            }
+            Visit::Cons => {
+                let cdr = done.pop().unwrap();
+                let car = done.pop().unwrap();
+                done.push(Value::cons(car, cdr));
+            }
+        }
+    }
+
+    Ok(done.pop().unwrap())
+}
+
+pub fn value_to_expr(e: &Value) -> ExprRef {
+    use dsl::*;
+
+    let mut todo = vec![Visit::Node(e)];
+    let mut done = vec![];
+
+    while let Some(visit) = todo.pop() {
+        match visit {
+            Visit::Node(Value::Int(n)) => done.push(int(n.clone())),
+            Visit::Node(Value::Nil) => done.push(atom("nil")),
+            Visit::Node(Value::Cons(a, b)) => {
+                todo.push(Visit::Cons);
+                todo.push(Visit::Node(b));
+                todo.push(Visit::Node(a));
+            }
+            Visit::Cons => {
+                let b = done.pop().unwrap();
+                let a = done.pop().unwrap();
+                done.push(app(app(atom("cons"), a), b));
+            }
+        }
+    }
+
+    done.pop().unwrap()
+}
+
+// Examples from the messages, in the order of messages/decoded*.txt.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sender::RecordedSender;
+
+    fn eval_str(s: &str) -> Value {
+        let dict = Dictionary::new();
+        expr_to_value(parse_expr(s).unwrap(), &Context::new(&dict)).unwrap()
+    }
+
+    fn eval_bool(s: &str) -> bool {
+        let dict = Dictionary::new();
+        expr_to_bool(parse_expr(s).unwrap(), &Context::new(&dict)).unwrap()
+    }
+
+    fn check(cases: &[(&str, &str)]) {
+        for &(expr, expected) in cases {
+            let expected = parse_sexp_str(expected).unwrap();
+            assert_eq!(eval_str(expr), expected, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        check(&[
+            ("ap inc 0", "1"),
+            ("ap inc 300", "301"),
+            ("ap inc -1", "0"),
+            ("ap dec 0", "-1"),
+            ("ap dec 1024", "1023"),
+            ("ap dec -2", "-3"),
+            ("ap ap add 2 1", "3"),
+            ("ap ap add 0 -1", "-1"),
+            ("ap ap mul 3 -2", "-6"),
+            ("ap ap div 4 3", "1"),
+            ("ap ap div 5 -3", "-1"),
+            ("ap ap div -5 3", "-1"),
+            ("ap neg -1", "1"),
+            ("ap inc ap inc 0", "2"),
+            ("ap pwr2 0", "1"),
+            ("ap pwr2 8", "256"),
+            // Numbers are unbounded.
+            ("ap pwr2 64", "18446744073709551616"),
+            ("ap ap mul 4294967296 4294967296", "18446744073709551616"),
+            ("ap ap add 9223372036854775807 1", "9223372036854775808"),
+            ("ap dec -9223372036854775808", "-9223372036854775809"),
+            ("ap ap div -18446744073709551617 4294967296", "-4294967296"),
+        ]);
+        assert!(eval_bool(
+            "ap ap lt 9223372036854775807 9223372036854775808"
+        ));
+        assert!(eval_bool("ap ap eq 1 1"));
+        assert!(!eval_bool("ap ap eq 0 -2"));
+        assert!(eval_bool("ap ap lt 0 1"));
+        assert!(!eval_bool("ap ap lt 0 0"));
+        assert!(!eval_bool("ap ap lt 19 -20"));
+    }
+
+    #[test]
+    fn test_modulate() {
+        let dict = Dictionary::new();
+        let ctx = Context::new(&dict);
+        for &(expr, bits) in &[
+            ("ap mod 0", "010"),
+            ("ap mod 1", "01100001"),
+            ("ap mod -1", "10100001"),
+            ("ap mod 256", "011110000100000000"),
+            ("ap mod nil", "00"),
+            ("ap mod ap ap cons 1 2", "110110000101100010"),
+            ("ap mod ( 1 , 2 )", "1101100001110110001000"),
+        ] {
+            let e = eval(parse_expr(expr).unwrap(), &ctx).unwrap();
+            assert_eq!(e.borrow().value.borrow().bits(), Some(bits), "{}", expr);
+        }
+        check(&[
+            ("ap dem ap mod -5", "-5"),
+            ("ap dem ap mod ( 1 , ( 2 , 3 ) , 4 )", "(1 (2 3) 4)"),
+            ("ap modem ap ap cons 1 ap inc 1", "(1 . 2)"),
+            (
+                "ap dem ap mod -18446744073709551616",
+                "-18446744073709551616",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_send() {
+        let dict = Dictionary::new();
+        let mut sender = RecordedSender::new(vec![parse_sexp_str("(1 . 2)").unwrap()]);
+        let res = {
+            let ctx = Context::with_sender(&dict, &mut sender);
+            expr_to_value(parse_expr("ap send ( 0 )").unwrap(), &ctx).unwrap()
+        };
+        assert_eq!(res, parse_sexp_str("(1 . 2)").unwrap());
+        assert_eq!(sender.sent, vec![parse_sexp_str("(0)").unwrap()]);
+
+        let err = expr_to_value(parse_expr("ap send ( 0 )").unwrap(), &Context::new(&dict));
+        assert_eq!(err.err().unwrap().to_string(), "send: no sender");
+    }
+
+    #[test]
+    fn test_errors() {
+        let dict = Dictionary::new();
+        let err = |s: &str| {
+            let e = parse_expr(s).unwrap();
+            eval(e, &Context::new(&dict)).err().unwrap().to_string()
+        };
+        assert_eq!(err("ap inc nil"), "inc: argument is not int");
+        assert_eq!(err("ap pwr2 -1"), "pwr2: bad exponent -1");
+        assert_eq!(err("ap ap div 1 0"), "div: 1 / 0");
+        assert_eq!(err("ap dem 1"), "dem: argument is not bits");
+        assert_eq!(err("ap ap ap foo 1 2 3"), "invalid function: foo");
+        assert_eq!(
+            err("ap ap checkerboard 100000000000000000000 nil"),
+            "checkerboard: size 100000000000000000000 is too large"
+        );
+
+        let err = |s: &str| parse_expr(s).err().unwrap().to_string();
+        assert_eq!(err("ap inc"), "unexpected end of expression");
+        assert_eq!(err("( 1 2 )"), "expected , or ) but found 2");
+        assert_eq!(err("1 2"), "unexpected 2 after the expression");
+        assert_eq!(
+            parse_functions("x = ap").err().unwrap().to_string(),
+            "line 1: unexpected end of expression"
+        );
+    }
+
+    #[test]
+    fn test_combinators() {
+        check(&[
+            ("ap ap ap s add inc 1", "3"),
+            ("ap ap ap s mul ap add 1 6", "42"),
+            ("ap ap ap c add 1 2", "3"),
+            ("ap ap ap b inc dec 5", "5"),
+            ("ap ap t 1 5", "1"),
+            ("ap ap f 1 5", "5"),
+            ("ap i 7", "7"),
+            ("ap ap i i 7", "7"),
+            ("ap ap ap if0 0 1 2", "1"),
+            ("ap ap ap if0 1 1 2", "2"),
+        ]);
+        assert!(eval_bool("ap ap t t ap inc 5"));
+        assert!(eval_bool("ap ap t t i"));
+    }
+
+    #[test]
+    fn test_lists() {
+        check(&[
+            ("ap car ap ap cons 1 2", "1"),
+            ("ap cdr ap ap cons 1 2", "2"),
+            ("ap ap ap cons 1 2 add", "3"),
+            ("ap ap vec 1 2", "(1 . 2)"),
+            ("ap ap ap vec 1 2 mul", "2"),
+            ("( )", "()"),
+            ("( 1 , 2 )", "(1 2)"),
+            ("( ( 1 ) , ap neg 2 )", "((1) -2)"),
+        ]);
+        assert!(eval_bool("ap nil 0"));
+        assert!(eval_bool("ap isnil nil"));
+        assert!(!eval_bool("ap isnil ap ap cons 1 2"));
+    }
+
+    // Builds (n n-1 ... 1) eagerly, as cons evaluates its arguments.
+    const COUNTDOWN: &str =
+        "countdown = ap ap s ap ap c ap eq 0 nil ap ap s cons ap ap b countdown dec";
+
+    #[test]
+    fn test_deep() {
+        let dict = parse_functions(COUNTDOWN).unwrap();
+        let ctx = Context::new(&dict);
+        let n = 10000;
+
+        let e = eval(parse_expr(&format!("ap countdown {}", n)).unwrap(), &ctx).unwrap();
+        let v = expr_to_value(e, &ctx).unwrap();
+        assert_eq!(demodulate(&modulate(&v)).unwrap(), v);
+
+        let xs = value_to_list(v);
+        assert_eq!(xs.len(), n);
+        assert_eq!(xs[0], Value::int(n));
+        assert_eq!(xs[n - 1], Value::int(1));
+    }
+
+    #[test]
+    fn test_draw() {
+        check(&[
+            ("ap draw ( )", "()"),
+            ("ap draw ( ap ap vec 1 1 )", "((1 . 1))"),
+            (
+                "ap draw ( ap ap vec 1 2 , ap ap vec 3 1 )",
+                "((1 . 2) (3 . 1))",
+            ),
+            ("ap ap checkerboard 2 0", "((0 . 0) (1 . 1))"),
+            (
+                "ap ap checkerboard 3 0",
+                "((0 . 0) (0 . 2) (1 . 1) (2 . 0) (2 . 2))",
+            ),
+            (
+                "ap multipledraw ( ( ap ap vec 1 1 ) , ( ap ap vec 2 2 ) )",
+                "(((1 . 1)) ((2 . 2)))",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_interact() {
+        check(&[
+            (
+                "ap ap ap interact statelessdraw nil ap ap vec 1 0",
+                "(() (((1 . 0))))",
+            ),
+            (
+                "ap ap ap interact statefuldraw nil ap ap vec 0 0",
+                "(((0 . 0)) (((0 . 0))))",
+            ),
+            (
+                "ap ap ap interact statefuldraw ( ap ap vec 0 0 ) ap ap vec 2 3",
+                "(((2 . 3) (0 . 0)) (((2 . 3) (0 . 0))))",
+            ),
+        ]);
+    }
+}
@@ -0,0 +1,29 @@
+// Copyright 2020 Google LLC
+// Copyright 2020 Team Spacecat
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The galaxy evaluator. GalaxyEngine runs the interaction protocol and sends
+// through a Sender; expr and value have the evaluator itself and the
+// modulated values it exchanges.
+
+mod engine;
+pub mod expr;
+mod sender;
+pub mod value;
+
+pub use engine::{GalaxyEngine, Image, Interaction, Step};
+pub use sender::{CurlSender, RecordedSender, Sender, DEFAULT_URL};
+pub use value::Value;
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
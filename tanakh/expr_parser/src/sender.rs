@@ -0,0 +1,121 @@
+// Copyright 2020 Google LLC
+// Copyright 2020 Team Spacecat
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::value::*;
+use crate::Result;
+use std::collections::VecDeque;
+
+// Where GalaxyEngine::interact sends the data of a flag=1 step. The response
+// is the next event.
+pub trait Sender {
+    fn send(&mut self, data: &Value) -> Result<Value>;
+}
+
+impl<F: FnMut(&Value) -> Result<Value>> Sender for F {
+    fn send(&mut self, data: &Value) -> Result<Value> {
+        self(data)
+    }
+}
+
+pub const DEFAULT_URL: &str = "https://icfpc2020-api.testkontur.ru/aliens/send?apiKey=REDACTED";
+
+// POSTs the modulated data with curl, e.g. to DEFAULT_URL with a real API key
+// or to http://localhost:8080/aliens/send of infra/local_server.
+pub struct CurlSender {
+    pub url: String,
+}
+
+impl CurlSender {
+    pub fn new(url: &str) -> CurlSender {
+        CurlSender {
+            url: url.to_string(),
+        }
+    }
+
+    // curl -X POST "<url>" -H "accept: */*" -H "Content-Type: text/plain" -d "111111011000010100000"
+    pub fn send_raw(&self, req: &str) -> Result<String> {
+        use std::process::Command;
+        let output = Command::new("curl")
+            .args(&[
+                "-s",
+                "-f",
+                "-X",
+                "POST",
+                &self.url,
+                "-H",
+                "accept: */*",
+                "-H",
+                "Content-Type: text/plain",
+                "-d",
+            ])
+            .arg(req)
+            .output()?;
+        if !output.status.success() {
+            return Err(format!("curl failed: {}", output.status).into());
+        }
+
+        Ok(String::from_utf8(output.stdout)?)
+    }
+}
+
+impl Sender for CurlSender {
+    fn send(&mut self, data: &Value) -> Result<Value> {
+        let resp = self.send_raw(&encode(&modulate(data)))?;
+        decode(resp.trim())
+            .and_then(|bits| demodulate(&bits))
+            .ok_or_else(|| format!("demodulate failed: {}", resp).into())
+    }
+}
+
+// Answers with recorded responses in order, and keeps what was sent.
+#[derive(Default)]
+pub struct RecordedSender {
+    pub responses: VecDeque<Value>,
+    pub sent: Vec<Value>,
+}
+
+impl RecordedSender {
+    pub fn new(responses: Vec<Value>) -> RecordedSender {
+        RecordedSender {
+            responses: responses.into(),
+            sent: vec![],
+        }
+    }
+
+    // One modulated response per line.
+    pub fn from_file(path: &str) -> Result<RecordedSender> {
+        let mut responses = vec![];
+        for (i, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let val = decode(line)
+                .and_then(|bits| demodulate(&bits))
+                .ok_or_else(|| format!("{}:{}: demodulate failed", path, i + 1))?;
+            responses.push(val);
+        }
+        Ok(RecordedSender::new(responses))
+    }
+}
+
+impl Sender for RecordedSender {
+    fn send(&mut self, data: &Value) -> Result<Value> {
+        self.sent.push(data.clone());
+        self.responses
+            .pop_front()
+            .ok_or_else(|| format!("no recorded response for {}", data.print()).into())
+    }
+}
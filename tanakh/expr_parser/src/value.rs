@@ -0,0 +1,482 @@
+// Copyright 2020 Google LLC
+// Copyright 2020 Team Spacecat
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use num_bigint::{BigInt, BigUint, Sign};
+use std::collections::VecDeque;
+use std::fmt::{self, Write};
+
+// Clone, PartialEq, Debug and Drop are written by hand below, as a session can
+// build values millions of cells deep. Nothing here recurses per cell.
+#[derive(Eq)]
+pub enum Value {
+    Int(BigInt),
+    Nil,
+    Cons(Box<Value>, Box<Value>),
+}
+
+impl Value {
+    pub fn nil() -> Value {
+        Value::Nil
+    }
+
+    pub fn cons(a: Value, b: Value) -> Value {
+        Value::Cons(Box::new(a), Box::new(b))
+    }
+
+    pub fn int<T: Into<BigInt>>(n: T) -> Value {
+        Value::Int(n.into())
+    }
+
+    pub fn list(v: Vec<Value>) -> Value {
+        let mut ret = Self::nil();
+        for v in v.into_iter().rev() {
+            ret = Self::cons(v, ret);
+        }
+        ret
+    }
+
+    fn modulate(&self, v: &mut Vec<bool>) {
+        let mut todo = vec![self];
+        while let Some(val) = todo.pop() {
+            match val {
+                Value::Int(n) => modulate_int(n, v),
+                Value::Nil => {
+                    v.push(false);
+                    v.push(false);
+                }
+                Value::Cons(hd, tl) => {
+                    v.push(true);
+                    v.push(true);
+                    todo.push(tl);
+                    todo.push(hd);
+                }
+            }
+        }
+    }
+
+    fn demodulate(it: &mut impl Iterator<Item = bool>) -> Option<Value> {
+        // The encoding is prefix, so the nodes are read first and built from
+        // the last one back: each cons takes the two values after it.
+        let mut nodes = vec![];
+        let mut missing = 1;
+        while missing > 0 {
+            let t0 = it.next()?;
+            let t1 = it.next()?;
+            missing -= 1;
+
+            nodes.push(match (t0, t1) {
+                (false, false) => Some(Value::Nil),
+                (true, true) => {
+                    missing += 2;
+                    None
+                }
+                (_, y) => Some(demodulate_int(y, it)?),
+            });
+        }
+
+        let mut done = vec![];
+        for node in nodes.into_iter().rev() {
+            match node {
+                Some(val) => done.push(val),
+                None => {
+                    let hd = done.pop().unwrap();
+                    let tl = done.pop().unwrap();
+                    done.push(Value::cons(hd, tl));
+                }
+            }
+        }
+        done.pop()
+    }
+
+    pub fn print(&self) -> String {
+        self.render(["(", " . ", ")"], "nil")
+    }
+
+    pub fn to_sexp(&self) -> String {
+        self.render(["(", " . ", ")"], "()")
+    }
+
+    pub fn to_raw(&self) -> String {
+        self.render(["ap ap cons ", " ", ""], "nil")
+    }
+
+    fn render(&self, cell: [&'static str; 3], nil: &str) -> String {
+        let mut s = String::new();
+        write_value(&mut s, self, cell, |s, val| match val {
+            Value::Int(n) => write!(s, "{}", n),
+            _ => s.write_str(nil),
+        })
+        .unwrap();
+        s
+    }
+}
+
+enum Token<'a> {
+    Val(&'a Value),
+    Str(&'static str),
+}
+
+// Writes val with cell[0], cell[1] and cell[2] before, between and after the
+// halves of each cell, and with `leaf` for the rest.
+fn write_value<W: Write>(
+    out: &mut W,
+    val: &Value,
+    cell: [&'static str; 3],
+    leaf: impl Fn(&mut W, &Value) -> fmt::Result,
+) -> fmt::Result {
+    let mut todo = vec![Token::Val(val)];
+    while let Some(token) = todo.pop() {
+        match token {
+            Token::Val(Value::Cons(hd, tl)) => {
+                out.write_str(cell[0])?;
+                todo.push(Token::Str(cell[2]));
+                todo.push(Token::Val(tl));
+                todo.push(Token::Str(cell[1]));
+                todo.push(Token::Val(hd));
+            }
+            Token::Val(val) => leaf(out, val)?,
+            Token::Str(s) => out.write_str(s)?,
+        }
+    }
+    Ok(())
+}
+
+impl Clone for Value {
+    fn clone(&self) -> Value {
+        // None builds a cell from the last two values.
+        let mut todo = vec![Some(self)];
+        let mut done = vec![];
+        while let Some(val) = todo.pop() {
+            match val {
+                Some(Value::Int(n)) => done.push(Value::Int(n.clone())),
+                Some(Value::Nil) => done.push(Value::Nil),
+                Some(Value::Cons(hd, tl)) => {
+                    todo.push(None);
+                    todo.push(Some(tl));
+                    todo.push(Some(hd));
+                }
+                None => {
+                    let tl = done.pop().unwrap();
+                    let hd = done.pop().unwrap();
+                    done.push(Value::cons(hd, tl));
+                }
+            }
+        }
+        done.pop().unwrap()
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        let mut todo = vec![(self, other)];
+        while let Some(pair) = todo.pop() {
+            match pair {
+                (Value::Int(a), Value::Int(b)) if a == b => {}
+                (Value::Nil, Value::Nil) => {}
+                (Value::Cons(a, b), Value::Cons(c, d)) => {
+                    todo.push((b, d));
+                    todo.push((a, c));
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+// Like #[derive(Debug)], but with integers in decimal: Cons(Int(1), Nil).
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_value(f, self, ["Cons(", ", ", ")"], |f, val| match val {
+            Value::Int(n) => write!(f, "Int({})", n),
+            _ => f.write_str("Nil"),
+        })
+    }
+}
+
+// Moves the cells out of val, so that dropping it does not recurse.
+fn take_cells(val: &mut Value, cells: &mut Vec<Value>) {
+    if let Value::Cons(hd, tl) = val {
+        for half in [hd, tl].iter_mut() {
+            if let Value::Cons(_, _) = ***half {
+                cells.push(std::mem::replace(&mut ***half, Value::Nil));
+            }
+        }
+    }
+}
+
+impl Drop for Value {
+    fn drop(&mut self) {
+        let mut cells = vec![];
+        take_cells(self, &mut cells);
+        while let Some(mut cell) = cells.pop() {
+            take_cells(&mut cell, &mut cells);
+        }
+    }
+}
+
+fn modulate_int(n: &BigInt, v: &mut Vec<bool>) {
+    let negative = n.sign() == Sign::Minus;
+    v.push(negative);
+    v.push(!negative);
+
+    // The nibbles of the magnitude, none for 0.
+    let nibbles = match n.sign() {
+        Sign::NoSign => vec![],
+        _ => n.magnitude().to_radix_be(16),
+    };
+
+    for _ in 0..nibbles.len() {
+        v.push(true);
+    }
+    v.push(false);
+
+    for nibble in nibbles {
+        for i in (0..4).rev() {
+            v.push((nibble >> i) & 1 == 1);
+        }
+    }
+}
+
+// After the sign bits: 1 for positive.
+fn demodulate_int(positive: bool, it: &mut impl Iterator<Item = bool>) -> Option<Value> {
+    let mut t = 0;
+    while it.next()? {
+        t += 1;
+    }
+    let mut v = BigUint::from(0u32);
+    for _ in 0..4 * t {
+        v = v << 1 | BigUint::from(it.next()? as u32);
+    }
+    let sign = if positive { Sign::Plus } else { Sign::Minus };
+    Some(Value::Int(BigInt::from_biguint(sign, v)))
+}
+
+pub fn modulate(v: &Value) -> Vec<bool> {
+    let mut ret = vec![];
+    v.modulate(&mut ret);
+    ret
+}
+
+pub fn demodulate(v: &[bool]) -> Option<Value> {
+    let mut it = v.iter().cloned();
+    let ret = Value::demodulate(&mut it)?;
+    match it.next() {
+        None => Some(ret),
+        Some(_) => None,
+    }
+}
+
+pub fn encode(v: &[bool]) -> String {
+    v.iter().map(|b| if *b { '1' } else { '0' }).collect()
+}
+
+// None unless s is all 0s and 1s.
+pub fn decode(s: &str) -> Option<Vec<bool>> {
+    s.chars()
+        .map(|c| match c {
+            '0' => Some(false),
+            '1' => Some(true),
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn value_to_list(v: Value) -> VecDeque<Value> {
+    let mut ret = VecDeque::new();
+    let mut v = v;
+    loop {
+        match &mut v {
+            Value::Nil => return ret,
+            Value::Cons(a, b) => {
+                ret.push_back(std::mem::replace(&mut **a, Value::Nil));
+                let tl = std::mem::replace(&mut **b, Value::Nil);
+                v = tl;
+            }
+            Value::Int(_) => panic!(),
+        }
+    }
+}
+
+#[test]
+fn test_mod() {
+    assert_eq!(modulate(&Value::Nil), vec![false, false]);
+}
+
+#[test]
+fn test_demod() {
+    let t = "1101100001111101100010110110001100110110010000";
+    let v = t.chars().map(|c| c == '1').collect::<Vec<_>>();
+    let v = demodulate(&v).unwrap();
+
+    let u = modulate(&v)
+        .into_iter()
+        .map(|b| if b { '1' } else { '0' })
+        .collect::<String>();
+    assert_eq!(t, u);
+}
+
+#[test]
+fn test_mod_bigint() {
+    let n = Value::Int("-18446744073709551616".parse().unwrap());
+    let bits = modulate(&n);
+    assert_eq!(
+        encode(&bits),
+        format!("10{}0{}{}", "1".repeat(17), "0001", "0".repeat(64))
+    );
+    assert_eq!(demodulate(&bits), Some(n));
+}
+
+#[test]
+fn test_demod_invalid() {
+    assert_eq!(decode("<html>403</html>"), None);
+    assert_eq!(decode("0 1"), None);
+    assert_eq!(demodulate(&decode("010").unwrap()), Some(Value::int(0)));
+    // Left-over bits after a whole value.
+    assert_eq!(demodulate(&decode("0100").unwrap()), None);
+}
+
+// A list being parsed.
+struct Open {
+    items: Vec<Value>,
+    // Where the item after a " . " is, if there was one.
+    tail: Option<usize>,
+}
+
+fn parse_sexp<'a>(it: &mut impl Iterator<Item = &'a str>) -> Option<Value> {
+    let mut open: Vec<Open> = vec![];
+    loop {
+        let s = it.next()?;
+        let val = match s {
+            "(" => {
+                open.push(Open {
+                    items: vec![],
+                    tail: None,
+                });
+                continue;
+            }
+            "." if !open.is_empty() => {
+                let top = open.last_mut().unwrap();
+                top.tail = Some(top.items.len());
+                continue;
+            }
+            ")" if !open.is_empty() => {
+                let Open { mut items, tail } = open.pop().unwrap();
+                match tail {
+                    Some(i) => {
+                        assert_eq!(items.len(), i + 1, "one value after .");
+                        let mut ret = items.pop().unwrap();
+                        for v in items.into_iter().rev() {
+                            ret = Value::cons(v, ret);
+                        }
+                        ret
+                    }
+                    None => Value::list(items),
+                }
+            }
+            _ => match s.parse::<BigInt>() {
+                Ok(n) => Value::Int(n),
+                Err(_) => unreachable!("{}", s),
+            },
+        };
+        match open.last_mut() {
+            Some(top) => top.items.push(val),
+            None => return Some(val),
+        }
+    }
+}
+
+pub fn parse_sexp_str(s: &str) -> Option<Value> {
+    let mut ns = String::new();
+    for c in s.chars() {
+        if c == '(' || c == ')' || c == '.' {
+            ns.push(' ');
+            ns.push(c);
+            ns.push(' ');
+        } else {
+            ns.push(c);
+        }
+    }
+    let mut it = ns.split_whitespace();
+    let ret = parse_sexp(&mut it)?;
+    assert!(it.next().is_none());
+    Some(ret)
+}
+
+#[test]
+fn test_parse_sexp() {
+    assert_eq!(parse_sexp_str("-123"), Some(Value::int(-123)));
+    assert_eq!(parse_sexp_str("()"), Some(Value::nil()));
+
+    assert_eq!(
+        parse_sexp_str("(1 . 2)"),
+        Some(Value::cons(Value::int(1), Value::int(2)))
+    );
+
+    assert_eq!(
+        parse_sexp_str("(1 2)"),
+        Some(Value::list(vec![Value::int(1), Value::int(2)]))
+    );
+
+    assert_eq!(
+        parse_sexp_str("(1 2 . 3)"),
+        Some(Value::cons(
+            Value::int(1),
+            Value::cons(Value::int(2), Value::int(3))
+        ))
+    );
+
+    assert_eq!(
+        parse_sexp_str("(1 2 3)"),
+        Some(Value::list(vec![
+            Value::int(1),
+            Value::int(2),
+            Value::int(3)
+        ]))
+    );
+}
+
+#[test]
+fn test_deep_values() {
+    // A million cells, deep in the cdr and then in the car.
+    const DEPTH: usize = 1_000_000;
+    let long_list = Value::list((0..DEPTH).map(|i| Value::int(i % 10)).collect());
+    let mut deep_car = Value::Nil;
+    for _ in 0..DEPTH {
+        deep_car = Value::cons(deep_car, Value::int(1));
+    }
+
+    for val in [long_list, deep_car].iter() {
+        let back = demodulate(&modulate(val)).unwrap();
+        assert_eq!(back, *val);
+        let sexp = back.to_sexp();
+        assert_eq!(parse_sexp_str(&sexp), Some(val.clone()));
+        assert_eq!(back.print().len(), sexp.len() + 1);
+        assert!(back.to_raw().starts_with("ap ap cons "));
+        assert!(format!("{:?}", back).starts_with("Cons("));
+    }
+    let xs = value_to_list(Value::list((0..DEPTH).map(Value::int).collect()));
+    assert_eq!(xs.len(), DEPTH);
+
+    assert_ne!(Value::int(1), Value::cons(Value::int(1), Value::Nil));
+    assert_eq!(
+        format!("{:?}", Value::list(vec![Value::int(1)])),
+        "Cons(Int(1), Nil)"
+    );
+    assert_eq!(
+        Value::list(vec![Value::int(1)]).to_raw(),
+        "ap ap cons 1 nil"
+    );
+}